@@ -0,0 +1,376 @@
+use crate::ops::{EvalError, Func, Funcs, Ops};
+use crate::lexer::TerminalEnd;
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// Base of digits of the magnitude
+const BASE: u64 = 1_000_000_000;
+/// Number of decimal digits in a digit of the magnitude
+const BASE_DIGITS: usize = 9;
+/// Limit of the number of bits of a power computed by `^`, which bounds its time and memory
+const MAX_POW_BITS: u64 = 1 << 20;
+/// Limit of the operand of factorial `!`, to prevent unbounded computation
+const MAX_FACT: u64 = 10_000;
+
+/// An arbitrary precision integer
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct BigInt {
+    neg: bool,
+    /// Digits in base `BASE`, the least significant first, without leading zeros, empty for 0
+    mag: Vec<u32>,
+}
+
+fn trim(mut mag: Vec<u32>) -> Vec<u32> {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    mag
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let s = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        r.push((s % BASE) as u32);
+        carry = s / BASE;
+    }
+    if carry > 0 {
+        r.push(carry as u32);
+    }
+    r
+}
+
+/// Computes `a - b`, requires `a >= b`
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &d) in a.iter().enumerate() {
+        let mut s = d as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if s < 0 {
+            s += BASE as i64;
+            borrow = 1;
+        }
+        r.push(s as u32);
+    }
+    trim(r)
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+    let mut r = vec![0u64; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0;
+        for (j, &y) in b.iter().enumerate() {
+            let t = r[i + j] + x as u64 * y as u64 + carry;
+            r[i + j] = t % BASE;
+            carry = t / BASE;
+        }
+        r[i + b.len()] += carry;
+    }
+    trim(r.into_iter().map(|d| d as u32).collect())
+}
+
+/// Computes quotient and remainder of `a / b`, requires nonzero `b`
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut q = vec![0u32; a.len()];
+    let mut r: Vec<u32> = Vec::new();
+    for i in (0..a.len()).rev() {
+        r.insert(0, a[i]);
+        r = trim(r);
+        // The largest digit d such that b * d <= r
+        let (mut lo, mut hi) = (0u64, BASE - 1);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            if cmp_mag(&mul_mag(b, &[mid as u32]), &r) != Ordering::Greater {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        if lo > 0 {
+            r = sub_mag(&r, &mul_mag(b, &[lo as u32]));
+        }
+        q[i] = lo as u32;
+    }
+    (trim(q), r)
+}
+
+impl BigInt {
+    fn new(neg: bool, mag: Vec<u32>) -> BigInt {
+        let mag = trim(mag);
+        BigInt {neg: neg && !mag.is_empty(), mag}
+    }
+
+    pub fn from_u64(mut v: u64) -> BigInt {
+        let mut mag = Vec::new();
+        while v > 0 {
+            mag.push((v % BASE) as u32);
+            v /= BASE;
+        }
+        BigInt::new(false, mag)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.neg
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        if self.neg || self.mag.len() > 3 {
+            return None;
+        }
+        self.mag.iter().rev().try_fold(0u64, |v, &d| v.checked_mul(BASE)?.checked_add(d as u64))
+    }
+
+    pub fn abs(&self) -> BigInt {
+        BigInt::new(false, self.mag.clone())
+    }
+
+    fn signed_add(&self, r_neg: bool, r_mag: &[u32]) -> BigInt {
+        if self.neg == r_neg {
+            return BigInt::new(self.neg, add_mag(&self.mag, r_mag));
+        }
+        match cmp_mag(&self.mag, r_mag) {
+            Ordering::Less => BigInt::new(r_neg, sub_mag(r_mag, &self.mag)),
+            _ => BigInt::new(self.neg, sub_mag(&self.mag, r_mag)),
+        }
+    }
+
+    /// Quotient rounded toward zero and remainder with the sign of `self`
+    pub fn divrem(&self, r: &BigInt) -> Result<(BigInt, BigInt), EvalError> {
+        if r.mag.is_empty() {
+            return Err(EvalError::DivisionByZero);
+        }
+        let (q, m) = divrem_mag(&self.mag, &r.mag);
+        Ok((BigInt::new(self.neg != r.neg, q), BigInt::new(self.neg, m)))
+    }
+
+    pub fn gcd(&self, r: &BigInt) -> BigInt {
+        let (mut a, mut b) = (self.abs(), r.abs());
+        while !b.is_zero() {
+            let m = divrem_mag(&a.mag, &b.mag).1;
+            a = b;
+            b = BigInt::new(false, m);
+        }
+        a
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, r: &BigInt) -> Ordering {
+        match (self.neg, r.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &r.mag),
+            (true, true) => cmp_mag(&r.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, r: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(r))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let Some((top, rest)) = self.mag.split_last() else {
+            return write!(f, "0");
+        };
+        if self.neg {
+            write!(f, "-")?;
+        }
+        write!(f, "{top}")?;
+        for d in rest.iter().rev() {
+            write!(f, "{d:0BASE_DIGITS$}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = ();
+    fn from_str(s: &str) -> Result<BigInt, ()> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(());
+        }
+        let mut mag = Vec::with_capacity(digits.len() / BASE_DIGITS + 1);
+        let mut end = digits.len();
+        while end > 0 {
+            let start = end.saturating_sub(BASE_DIGITS);
+            mag.push(digits[start..end].parse::<u32>().map_err(|_| ())?);
+            end = start;
+        }
+        Ok(BigInt::new(neg, mag))
+    }
+}
+
+impl Ops<BigInt> for BigInt {
+    const TYPE: &'static str = "bigint";
+    fn zero() -> Option<BigInt> {
+        Some(BigInt::default())
+    }
+    fn one() -> Option<BigInt> {
+        Some(BigInt::from_u64(1))
+    }
+    fn annihilates() -> bool {
+        true
+    }
+    fn neg_involutive() -> bool {
+        true
+    }
+    fn neg(&self) -> Result<BigInt, EvalError> {
+        Ok(BigInt::new(!self.neg, self.mag.clone()))
+    }
+    fn add(&self, r: &BigInt) -> Result<BigInt, EvalError> {
+        Ok(self.signed_add(r.neg, &r.mag))
+    }
+    fn sub(&self, r: &BigInt) -> Result<BigInt, EvalError> {
+        Ok(self.signed_add(!r.neg, &r.mag))
+    }
+    fn mul(&self, r: &BigInt) -> Result<BigInt, EvalError> {
+        Ok(BigInt::new(self.neg != r.neg, mul_mag(&self.mag, &r.mag)))
+    }
+    fn div(&self, r: &BigInt) -> Result<BigInt, EvalError> {
+        Ok(self.divrem(r)?.0)
+    }
+    fn rem(&self, r: &BigInt) -> Result<BigInt, EvalError> {
+        Ok(self.divrem(r)?.1)
+    }
+    fn pow(&self, r: &BigInt) -> Result<BigInt, EvalError> {
+        if r.neg {
+            return Err(EvalError::InvalidArgument("^"));
+        }
+        let Some(&top) = self.mag.last() else {
+            return Ok(BigInt::from_u64(r.is_zero() as u64));
+        };
+        if self.mag.len() == 1 && top == 1 {
+            let odd = r.mag.first().is_some_and(|d| d % 2 == 1);
+            return Ok(BigInt::new(self.neg && odd, vec![1]));
+        }
+        // The power has at most the bits of the base times the exponent, and a digit of the
+        // magnitude has at most 30 bits
+        let bits = (self.mag.len() as u64 - 1) * 30 + u64::from(u32::BITS - top.leading_zeros());
+        let mut e = match r.to_u64() {
+            Some(e) if bits.saturating_mul(e) <= MAX_POW_BITS => e,
+            _ => return Err(EvalError::Overflow),
+        };
+        let mut base = self.clone();
+        let mut v = BigInt::from_u64(1);
+        while e > 0 {
+            if e & 1 == 1 {
+                v = v.mul(&base)?;
+            }
+            e >>= 1;
+            if e > 0 {
+                base = base.mul(&base)?;
+            }
+        }
+        Ok(v)
+    }
+    fn compare(&self, r: &BigInt) -> Result<Option<Ordering>, EvalError> {
+        Ok(Some(self.cmp(r)))
+    }
+    fn fact(&self) -> Result<BigInt, EvalError> {
+        if self.neg {
+            return Err(EvalError::InvalidArgument("!"));
+        }
+        let n = match self.to_u64() {
+            Some(n) if n <= MAX_FACT => n,
+            _ => return Err(EvalError::Overflow),
+        };
+        let mut v = BigInt::from_u64(1);
+        for k in 2..=n {
+            v = v.mul(&BigInt::from_u64(k))?;
+        }
+        Ok(v)
+    }
+}
+
+impl Funcs for BigInt {
+    const FUNCS: &'static [Func<BigInt>] = &[
+        Func {name: "abs", arity: 1, f: |a| Ok(a[0].abs())},
+        Func {name: "min", arity: 2, f: |a| Ok(a[0].clone().min(a[1].clone()))},
+        Func {name: "max", arity: 2, f: |a| Ok(a[0].clone().max(a[1].clone()))},
+        Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+    ];
+}
+
+impl TerminalEnd for BigInt {
+    fn pattern(c: char) -> bool {
+        !c.is_ascii_digit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::eval;
+
+    fn n(s: &str) -> Result<String, String> {
+        eval::<BigInt>(s)
+    }
+
+    #[test]
+    fn sums_and_differences() {
+        assert_eq!(n("999999999 + 1"), Ok(String::from("1000000000")));
+        assert_eq!(n("999999999999999999999999999 + 1"), Ok(String::from("1000000000000000000000000000")));
+        assert_eq!(n("1000000000000000000 - 1"), Ok(String::from("999999999999999999")));
+        assert_eq!(n("1 - 1000000000000000000"), Ok(String::from("-999999999999999999")));
+        assert_eq!(n("-5 + 5"), Ok(String::from("0")));
+        assert_eq!(n("100000000000000000000000000000 + 1 - 100000000000000000000000000000"), Ok(String::from("1")));
+    }
+
+    #[test]
+    fn products() {
+        assert_eq!(n("99999999999999999999 * 99999999999999999999"), Ok(String::from("9999999999999999999800000000000000000001")));
+        assert_eq!(n("-123456789012 * 1000000000"), Ok(String::from("-123456789012000000000")));
+        assert_eq!(n("-7 * -6"), Ok(String::from("42")));
+        assert_eq!(n("0 * -123456789012345678901234567890"), Ok(String::from("0")));
+        assert_eq!(n("25!"), Ok(String::from("15511210043330985984000000")));
+    }
+
+    #[test]
+    fn quotients_and_remainders() {
+        assert_eq!(n("7 / 2"), Ok(String::from("3")));
+        assert_eq!(n("-7 / 2"), Ok(String::from("-3")));
+        assert_eq!(n("7 % -2"), Ok(String::from("1")));
+        assert_eq!(n("-7 % 2"), Ok(String::from("-1")));
+        assert_eq!(n("9999999999999999999800000000000000000001 / 99999999999999999999"), Ok(String::from("99999999999999999999")));
+        assert_eq!(n("(10 ^ 30 + 7) % 1000000007"), Ok(String::from("999657014")));
+        assert_eq!(n("1 / 0"), Err(String::from("division by zero")));
+        assert_eq!(n("1 % 0"), Err(String::from("division by zero")));
+    }
+
+    #[test]
+    fn powers() {
+        assert_eq!(n("2 ^ 100"), Ok(String::from("1267650600228229401496703205376")));
+        assert_eq!(n("(-3) ^ 3"), Ok(String::from("-27")));
+        assert_eq!(n("0 ^ 0"), Ok(String::from("1")));
+        assert_eq!(n("0 ^ 100000000000000000000"), Ok(String::from("0")));
+        assert_eq!(n("1 ^ 100000000000000000000"), Ok(String::from("1")));
+        assert_eq!(n("(-1) ^ 100000000000000000001"), Ok(String::from("-1")));
+        assert_eq!(n("2 ^ -1"), Err(String::from("invalid argument of ^")));
+        assert_eq!(n("(2 ^ 100000) % 1000"), Ok(String::from("376")));
+        assert_eq!(n("2 ^ 524289"), Err(String::from("arithmetic overflow")));
+        assert_eq!(n("1000000000 ^ 40000"), Err(String::from("arithmetic overflow")));
+        assert_eq!(n("(10 ^ 1000) ^ 1000"), Err(String::from("arithmetic overflow")));
+    }
+}
@@ -0,0 +1,226 @@
+use crate::ops::{EvalError, Func, Funcs, Ops};
+use crate::lexer::{float_literal, parse_float, TerminalEnd};
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// A complex number with `f64` real and imaginary parts
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex {re, im}
+    }
+
+    fn abs(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    fn exp(self) -> Complex {
+        let m = self.re.exp();
+        Complex::new(m * self.im.cos(), m * self.im.sin())
+    }
+
+    /// Principal value of the natural logarithm
+    fn ln(self) -> Result<Complex, EvalError> {
+        if self == Complex::default() {
+            return Err(EvalError::InvalidArgument("ln"));
+        }
+        Ok(Complex::new(self.abs().ln(), self.arg()))
+    }
+
+    /// Principal square root, exact for squares of Gaussian integers
+    fn sqrt(self) -> Complex {
+        let m = self.abs();
+        let im = ((m - self.re) / 2.0).sqrt();
+        Complex::new(((m + self.re) / 2.0).sqrt(), if self.im < 0.0 { -im } else { im })
+    }
+
+    /// Integer power by repeated squaring, exact for small Gaussian integers
+    fn powi(self, mut e: u64) -> Complex {
+        let (mut base, mut v) = (self, Complex::new(1.0, 0.0));
+        while e > 0 {
+            if e & 1 == 1 {
+                v = mul(v, base);
+            }
+            e >>= 1;
+            base = mul(base, base);
+        }
+        v
+    }
+}
+
+fn mul(l: Complex, r: Complex) -> Complex {
+    Complex::new(l.re * r.re - l.im * r.im, l.re * r.im + l.im * r.re)
+}
+
+fn div(l: Complex, r: Complex) -> Result<Complex, EvalError> {
+    let d = r.re * r.re + r.im * r.im;
+    if d == 0.0 {
+        return Err(EvalError::DivisionByZero);
+    }
+    Ok(Complex::new((l.re * r.re + l.im * r.im) / d, (l.im * r.re - l.re * r.im) / d))
+}
+
+/// Displays in the form `a+bi`, omitting a zero part and a unit coefficient of `i`
+impl Display for Complex {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        if self.im == 0.0 {
+            return write!(f, "{}", self.re);
+        }
+        if self.re != 0.0 {
+            write!(f, "{}", self.re)?;
+        }
+        if self.im.is_sign_negative() {
+            write!(f, "-")?;
+        } else if self.re != 0.0 {
+            write!(f, "+")?;
+        }
+        match self.im.abs() {
+            1.0 => write!(f, "i"),
+            im => write!(f, "{im}i"),
+        }
+    }
+}
+
+/// Parses an imaginary part like `4i`, `i`, or `-2.5i`
+fn imaginary(s: &str) -> Result<f64, ()> {
+    match s.strip_suffix('i').ok_or(())? {
+        "" | "+" => Ok(1.0),
+        "-" => Ok(-1.0),
+        s => parse_float(s).ok_or(()),
+    }
+}
+
+impl FromStr for Complex {
+    type Err = ();
+    /// Parses a real number `3`, an imaginary number `4i`, or both parts `3+4i`
+    fn from_str(s: &str) -> Result<Complex, ()> {
+        if !s.ends_with('i') {
+            return Ok(Complex::new(parse_float(s).ok_or(())?, 0.0));
+        }
+        // The sign of the imaginary part, which is not the sign of an exponent
+        let sign = s.char_indices().skip(1)
+            .filter(|&(i, c)| (c == '+' || c == '-') && !s[..i].ends_with(['e', 'E']))
+            .last();
+        match sign {
+            Some((i, _)) => Ok(Complex::new(parse_float(&s[..i]).ok_or(())?, imaginary(&s[i..])?)),
+            None => Ok(Complex::new(0.0, imaginary(s)?)),
+        }
+    }
+}
+
+impl Ops<Complex> for Complex {
+    const TYPE: &'static str = "complex";
+    fn zero() -> Option<Complex> {
+        Some(Complex::default())
+    }
+    fn one() -> Option<Complex> {
+        Some(Complex::new(1.0, 0.0))
+    }
+    fn neg_involutive() -> bool {
+        true
+    }
+    fn signed_zero() -> bool {
+        true
+    }
+    fn neg(&self) -> Result<Complex, EvalError> {
+        // Subtraction from zero avoids negative zeros, which would change the branch of `arg`
+        Ok(Complex::new(0.0 - self.re, 0.0 - self.im))
+    }
+    fn add(&self, r: &Complex) -> Result<Complex, EvalError> {
+        Ok(Complex::new(self.re + r.re, self.im + r.im))
+    }
+    fn sub(&self, r: &Complex) -> Result<Complex, EvalError> {
+        Ok(Complex::new(self.re - r.re, self.im - r.im))
+    }
+    fn mul(&self, r: &Complex) -> Result<Complex, EvalError> {
+        Ok(mul(*self, *r))
+    }
+    fn div(&self, r: &Complex) -> Result<Complex, EvalError> {
+        div(*self, *r)
+    }
+    fn rem(&self, _r: &Complex) -> Result<Complex, EvalError> {
+        Err(Self::unsupported("%"))
+    }
+    /// Integer powers are computed by multiplication, other powers by the principal logarithm
+    fn pow(&self, r: &Complex) -> Result<Complex, EvalError> {
+        if r.im == 0.0 && r.re.fract() == 0.0 && r.re.abs() <= u32::MAX as f64 {
+            let p = self.powi(r.re.abs() as u64);
+            return if r.re < 0.0 { div(Complex::new(1.0, 0.0), p) } else { Ok(p) };
+        }
+        if *self == Complex::default() {
+            return if r.re > 0.0 { Ok(Complex::default()) } else { Err(EvalError::InvalidArgument("^")) };
+        }
+        Ok(mul(*r, self.ln()?).exp())
+    }
+    fn compare(&self, _r: &Complex) -> Result<Option<Ordering>, EvalError> {
+        Err(Self::unsupported("comparison"))
+    }
+}
+
+impl Funcs for Complex {
+    const FUNCS: &'static [Func<Complex>] = &[
+        Func {name: "abs", arity: 1, f: |a| Ok(Complex::new(a[0].abs(), 0.0))},
+        Func {name: "arg", arity: 1, f: |a| Ok(Complex::new(a[0].arg(), 0.0))},
+        Func {name: "conj", arity: 1, f: |a| Ok(Complex::new(a[0].re, -a[0].im))},
+        Func {name: "re", arity: 1, f: |a| Ok(Complex::new(a[0].re, 0.0))},
+        Func {name: "im", arity: 1, f: |a| Ok(Complex::new(a[0].im, 0.0))},
+        Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        Func {name: "sqrt", arity: 1, f: |a| Ok(a[0].sqrt())},
+        Func {name: "exp", arity: 1, f: |a| Ok(a[0].exp())},
+        Func {name: "ln", arity: 1, f: |a| a[0].ln()},
+    ];
+}
+
+impl TerminalEnd for Complex {
+    fn pattern(c: char) -> bool {
+        !(c.is_ascii_digit() || c == '.')
+    }
+    /// A literal is a real number, optionally followed by the imaginary unit `i`, or `i` alone
+    fn literal(s: &str) -> usize {
+        let n = float_literal(s);
+        let ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        match s[n..].strip_prefix('i') {
+            Some(rest) if !rest.starts_with(ident) => n + 1,
+            _ => n,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Complex;
+    use crate::tests::eval;
+
+    fn c(s: &str) -> Result<String, String> {
+        eval::<Complex>(s)
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(c("(3 + 4i) * (1 - 2i)"), Ok(String::from("11-2i")));
+        assert_eq!(c("(1 + 2i) / (3 - 4i)"), Ok(String::from("-0.2+0.4i")));
+        assert_eq!(c("2.5i - 0.5"), Ok(String::from("-0.5+2.5i")));
+        assert_eq!(c("i ^ 2"), Ok(String::from("-1")));
+        assert_eq!(c("-i"), Ok(String::from("-i")));
+        assert_eq!(c("(1 + i) / 0"), Err(String::from("division by zero")));
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(c("abs(3 + 4i)"), Ok(String::from("5")));
+        assert_eq!(c("conj(2 - 3i)"), Ok(String::from("2+3i")));
+        assert_eq!(c("arg(i)"), Ok(String::from("1.5707963267948966")));
+        assert_eq!(c("sqrt(-4)"), Ok(String::from("2i")));
+        assert_eq!(c("min(i, 1)"), Err(String::from("unknown function min")));
+    }
+}
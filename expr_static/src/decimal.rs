@@ -0,0 +1,327 @@
+use crate::ops::{EvalError, Func, Funcs, Ops};
+use crate::lexer::TerminalEnd;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// Maximum number of fractional digits
+pub const MAX_SCALE: u32 = 18;
+
+/// Rounding of results with more fractional digits than the scale
+#[derive(Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// To the nearest value, ties to the value with an even last digit
+    HalfEven,
+    /// To the nearest value, ties away from zero
+    HalfUp,
+    /// Toward zero
+    Truncate,
+}
+impl FromStr for Rounding {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Rounding, ()> {
+        match s {
+            "half-even" => Ok(Rounding::HalfEven),
+            "half-up" => Ok(Rounding::HalfUp),
+            "truncate" => Ok(Rounding::Truncate),
+            _ => Err(()),
+        }
+    }
+}
+
+thread_local! {
+    static SCALE: Cell<u32> = const { Cell::new(4) };
+    static ROUNDING: Cell<Rounding> = const { Cell::new(Rounding::HalfEven) };
+}
+/// Sets the number of fractional digits of values, at most `MAX_SCALE`
+pub fn set_scale(s: u32) {
+    SCALE.with(|c| c.set(s.min(MAX_SCALE)));
+}
+fn scale() -> u32 {
+    SCALE.with(|c| c.get())
+}
+pub fn set_rounding(r: Rounding) {
+    ROUNDING.with(|c| c.set(r));
+}
+fn rounding() -> Rounding {
+    ROUNDING.with(|c| c.get())
+}
+
+/// `10 ^ scale`, the number of units in 1
+fn unit() -> i128 {
+    10i128.pow(scale())
+}
+
+/// Product of `a` and `b` as the high and low halves of a 256-bit number
+fn mul_wide(a: u128, b: u128) -> (u128, u128) {
+    const LOW: u128 = u64::MAX as u128;
+    let (a1, a0, b1, b0) = (a >> 64, a & LOW, b >> 64, b & LOW);
+    let (p00, p01, p10, p11) = (a0 * b0, a0 * b1, a1 * b0, a1 * b1);
+    let mid = (p00 >> 64) + (p01 & LOW) + (p10 & LOW);
+    (p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64), (p00 & LOW) | (mid << 64))
+}
+
+/// Quotient and remainder of the 256-bit number `(hi, lo)` divided by `d`, `None` if the
+/// quotient does not fit into 128 bits
+fn div_wide((hi, lo): (u128, u128), d: u128) -> Option<(u128, u128)> {
+    if hi >= d {
+        return None;
+    }
+    if hi == 0 {
+        return Some((lo / d, lo % d));
+    }
+    // Long division by bits, the remainder is less than `d` but may take 129 bits when shifted
+    let (mut q, mut r) = (0u128, hi);
+    for i in (0..128).rev() {
+        let carry = r >> 127 == 1;
+        r = r << 1 | (lo >> i & 1);
+        q <<= 1;
+        if carry || r >= d {
+            r = r.wrapping_sub(d);
+            q |= 1;
+        }
+    }
+    Some((q, r))
+}
+
+/// Divides the 256-bit magnitude `n` by `d` with rounding by the current `Rounding` mode,
+/// and negates the quotient if `neg`
+fn round_div_wide(n: (u128, u128), d: u128, neg: bool) -> Result<i128, EvalError> {
+    if d == 0 {
+        return Err(EvalError::DivisionByZero);
+    }
+    let (q, r) = div_wide(n, d).ok_or(EvalError::Overflow)?;
+    let away = r != 0 && match (rounding(), r.cmp(&(d - r))) {
+        (Rounding::Truncate, _) | (_, Ordering::Less) => false,
+        (_, Ordering::Greater) | (Rounding::HalfUp, Ordering::Equal) => true,
+        (Rounding::HalfEven, Ordering::Equal) => q % 2 != 0,
+    };
+    let q = q.checked_add(away as u128).ok_or(EvalError::Overflow)?;
+    let v = if neg { 0i128.checked_sub_unsigned(q) } else { i128::try_from(q).ok() };
+    v.ok_or(EvalError::Overflow)
+}
+
+/// Divides `n` by `d` with rounding by the current `Rounding` mode
+fn round_div(n: i128, d: i128) -> Result<i128, EvalError> {
+    round_div_wide((0, n.unsigned_abs()), d.unsigned_abs(), (n < 0) != (d < 0))
+}
+
+/// A decimal fixed-point number with `scale` fractional digits
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Decimal {
+    /// The value multiplied by `10 ^ scale`
+    units: i128,
+}
+
+impl Decimal {
+    fn int(v: i128) -> Result<Decimal, EvalError> {
+        Ok(Decimal {units: v.checked_mul(unit()).ok_or(EvalError::Overflow)?})
+    }
+
+    /// Rounds to an integer by the current `Rounding` mode
+    fn round(self) -> Result<Decimal, EvalError> {
+        Decimal::int(round_div(self.units, unit())?)
+    }
+
+    /// The largest integer not greater than the value
+    fn floor(self) -> Result<Decimal, EvalError> {
+        Decimal::int(self.units.div_euclid(unit()))
+    }
+
+    fn ceil(self) -> Result<Decimal, EvalError> {
+        let u = unit();
+        Decimal::int(self.units.div_euclid(u) + (self.units.rem_euclid(u) != 0) as i128)
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let (u, s) = (unit().unsigned_abs(), scale() as usize);
+        let a = self.units.unsigned_abs();
+        if self.units < 0 {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", a / u)?;
+        if s > 0 {
+            write!(f, ".{:0s$}", a % u)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = ();
+    /// Parses a number with an optional fractional part, rounding extra fractional digits
+    fn from_str(s: &str) -> Result<Decimal, ()> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(d) => (true, d),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (int, frac) = digits.split_once('.').unwrap_or((digits, ""));
+        if int.is_empty() && frac.is_empty() || !(int.bytes().chain(frac.bytes()).all(|c| c.is_ascii_digit())) {
+            return Err(());
+        }
+        // Fractional digits beyond the scale only matter for rounding, by the first of them and
+        // by whether any of the others is not zero
+        let (kept, extra) = frac.split_at(frac.len().min(scale() as usize));
+        let n = format!("0{int}{kept}").parse::<u128>().map_err(|_| ())?;
+        let (n, d) = match extra.as_bytes() {
+            [] => ((0, n.checked_mul(10u128.pow(scale() - kept.len() as u32)).ok_or(())?), 1),
+            [first, rest @ ..] => {
+                let last = u128::from(first - b'0') * 10 + u128::from(rest.iter().any(|&c| c != b'0'));
+                let (hi, lo) = mul_wide(n, 100);
+                let (lo, carry) = lo.overflowing_add(last);
+                ((hi + u128::from(carry), lo), 100)
+            }
+        };
+        Ok(Decimal {units: round_div_wide(n, d, neg).map_err(|_| ())?})
+    }
+}
+
+impl Ops<Decimal> for Decimal {
+    const TYPE: &'static str = "decimal";
+    fn zero() -> Option<Decimal> {
+        Some(Decimal::default())
+    }
+    fn one() -> Option<Decimal> {
+        Decimal::int(1).ok()
+    }
+    fn annihilates() -> bool {
+        true
+    }
+    fn neg_involutive() -> bool {
+        true
+    }
+    fn neg(&self) -> Result<Decimal, EvalError> {
+        Ok(Decimal {units: self.units.checked_neg().ok_or(EvalError::Overflow)?})
+    }
+    fn add(&self, r: &Decimal) -> Result<Decimal, EvalError> {
+        Ok(Decimal {units: self.units.checked_add(r.units).ok_or(EvalError::Overflow)?})
+    }
+    fn sub(&self, r: &Decimal) -> Result<Decimal, EvalError> {
+        Ok(Decimal {units: self.units.checked_sub(r.units).ok_or(EvalError::Overflow)?})
+    }
+    /// Multiplication, rounded like division if the product has more fractional digits. The
+    /// product of units takes up to 256 bits before it is scaled back.
+    fn mul(&self, r: &Decimal) -> Result<Decimal, EvalError> {
+        let p = mul_wide(self.units.unsigned_abs(), r.units.unsigned_abs());
+        let neg = (self.units < 0) != (r.units < 0);
+        Ok(Decimal {units: round_div_wide(p, unit().unsigned_abs(), neg)?})
+    }
+    fn div(&self, r: &Decimal) -> Result<Decimal, EvalError> {
+        let n = mul_wide(self.units.unsigned_abs(), unit().unsigned_abs());
+        let neg = (self.units < 0) != (r.units < 0);
+        Ok(Decimal {units: round_div_wide(n, r.units.unsigned_abs(), neg)?})
+    }
+    fn rem(&self, r: &Decimal) -> Result<Decimal, EvalError> {
+        if r.units == 0 {
+            return Err(EvalError::DivisionByZero);
+        }
+        Ok(Decimal {units: self.units.checked_rem(r.units).ok_or(EvalError::Overflow)?})
+    }
+    /// Power with an integer exponent, each multiplication is rounded
+    fn pow(&self, r: &Decimal) -> Result<Decimal, EvalError> {
+        if r.units % unit() != 0 {
+            return Err(EvalError::InvalidArgument("^"));
+        }
+        let mut e = (r.units / unit()).unsigned_abs();
+        let (mut base, mut v) = (*self, Decimal::int(1)?);
+        while e > 0 {
+            if e & 1 == 1 {
+                v = v.mul(&base)?;
+            }
+            e >>= 1;
+            if e > 0 {
+                base = base.mul(&base)?;
+            }
+        }
+        if r.units < 0 {
+            Decimal::int(1)?.div(&v)
+        } else {
+            Ok(v)
+        }
+    }
+    fn compare(&self, r: &Decimal) -> Result<Option<Ordering>, EvalError> {
+        Ok(Some(self.cmp(r)))
+    }
+}
+
+impl Funcs for Decimal {
+    const FUNCS: &'static [Func<Decimal>] = &[
+        Func {name: "abs", arity: 1, f: |a| if a[0].units < 0 { a[0].neg() } else { Ok(a[0]) }},
+        Func {name: "min", arity: 2, f: |a| Ok(a[0].min(a[1]))},
+        Func {name: "max", arity: 2, f: |a| Ok(a[0].max(a[1]))},
+        Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        Func {name: "floor", arity: 1, f: |a| a[0].floor()},
+        Func {name: "ceil", arity: 1, f: |a| a[0].ceil()},
+        Func {name: "round", arity: 1, f: |a| a[0].round()},
+    ];
+}
+
+impl TerminalEnd for Decimal {
+    fn pattern(c: char) -> bool {
+        !(c.is_ascii_digit() || c == '.')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::eval;
+
+    fn m(scale: u32, rounding: Rounding, s: &str) -> Result<String, String> {
+        set_scale(scale);
+        set_rounding(rounding);
+        eval::<Decimal>(s)
+    }
+
+    #[test]
+    fn wide_product_and_quotient() {
+        assert_eq!(mul_wide(u128::MAX, u128::MAX), (u128::MAX - 1, 1));
+        assert_eq!(mul_wide(1 << 100, 1 << 100), (1 << 72, 0));
+        assert_eq!(div_wide((u128::MAX - 1, 1), u128::MAX), Some((u128::MAX, 0)));
+        assert_eq!(div_wide((1 << 72, 5), 1 << 100), Some((1 << 100, 5)));
+        assert_eq!(div_wide((1, 0), 3), Some((u128::MAX / 3, 1)));
+        assert_eq!(div_wide((3, 0), 3), None);
+    }
+
+    #[test]
+    fn full_scale() {
+        assert_eq!(m(18, Rounding::HalfEven, "14 * 14"), Ok(String::from("196.000000000000000000")));
+        assert_eq!(m(18, Rounding::HalfEven, "200 / 7"), Ok(String::from("28.571428571428571429")));
+        assert_eq!(m(18, Rounding::Truncate, "-200 / 7"), Ok(String::from("-28.571428571428571428")));
+        assert_eq!(m(18, Rounding::HalfEven, "-0.000000000000000001 * 0.5"), Ok(String::from("0.000000000000000000")));
+        assert_eq!(m(18, Rounding::HalfEven, "10000000000000000000 * 10"), Ok(String::from("100000000000000000000.000000000000000000")));
+        assert_eq!(m(18, Rounding::HalfEven, "100000000000000000000 * 10"), Err(String::from("arithmetic overflow")));
+        assert_eq!(m(18, Rounding::HalfEven, "1 / 0"), Err(String::from("division by zero")));
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(m(0, Rounding::HalfEven, "5 / 2"), Ok(String::from("2")));
+        assert_eq!(m(0, Rounding::HalfEven, "7 / 2"), Ok(String::from("4")));
+        assert_eq!(m(0, Rounding::HalfEven, "-5 / 2"), Ok(String::from("-2")));
+        assert_eq!(m(0, Rounding::HalfUp, "-5 / 2"), Ok(String::from("-3")));
+        assert_eq!(m(0, Rounding::Truncate, "-7 / 2"), Ok(String::from("-3")));
+        assert_eq!(m(2, Rounding::HalfEven, "0.05 * 0.5"), Ok(String::from("0.02")));
+        assert_eq!(m(2, Rounding::HalfUp, "0.05 * 0.5"), Ok(String::from("0.03")));
+    }
+
+    #[test]
+    fn long_fractions() {
+        let tiny = format!("0.{}9", "0".repeat(45));
+        assert_eq!(m(0, Rounding::HalfEven, &tiny), Ok(String::from("0")));
+        assert_eq!(m(4, Rounding::HalfUp, &format!("-{tiny}")), Ok(String::from("0.0000")));
+        let half = format!("0.5{}1", "0".repeat(40));
+        assert_eq!(m(0, Rounding::HalfEven, &half), Ok(String::from("1")));
+        assert_eq!(m(0, Rounding::HalfEven, "0.50000000000000000000000000000000000000000"), Ok(String::from("0")));
+        assert_eq!(m(0, Rounding::Truncate, &half), Ok(String::from("0")));
+        assert_eq!(m(2, Rounding::HalfUp, "-0.125"), Ok(String::from("-0.13")));
+        assert_eq!(m(2, Rounding::HalfEven, "0.12999999999999999999999999999999999999999"), Ok(String::from("0.13")));
+        let max = "170141183460469231731687303715884105727";
+        assert_eq!(m(0, Rounding::HalfEven, &format!("{max}.4999999999")), Ok(String::from(max)));
+        assert!(m(0, Rounding::HalfEven, &format!("{max}.5")).is_err());
+        assert!(m(1, Rounding::HalfEven, max).is_err());
+    }
+}
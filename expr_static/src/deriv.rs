@@ -0,0 +1,216 @@
+use crate::expr::{Expr, Op1Kind, Op2Kind};
+use crate::ops::EvalError;
+
+fn num(v: f64) -> Expr<f64> {
+    Expr::Value {v}
+}
+
+fn is_num(e: &Expr<f64>, c: f64) -> bool {
+    matches!(e, Expr::Value{v} if *v == c)
+}
+
+fn neg(e: Expr<f64>) -> Expr<f64> {
+    if is_num(&e, 0.0) {
+        return e;
+    }
+    Expr::Op1 {op: Op1Kind::Minus, child: Box::new(e)}
+}
+
+// The constructors below drop terms that are identically zero by a differentiation rule,
+// for example the derivative of a constant factor in the product rule.
+
+fn op2(op: Op2Kind, l: Expr<f64>, r: Expr<f64>) -> Expr<f64> {
+    Expr::Op2 {op, l: Box::new(l), r: Box::new(r)}
+}
+
+fn add(l: Expr<f64>, r: Expr<f64>) -> Expr<f64> {
+    if is_num(&l, 0.0) {
+        r
+    } else if is_num(&r, 0.0) {
+        l
+    } else {
+        op2(Op2Kind::Add, l, r)
+    }
+}
+
+fn sub(l: Expr<f64>, r: Expr<f64>) -> Expr<f64> {
+    if is_num(&r, 0.0) {
+        l
+    } else if is_num(&l, 0.0) {
+        neg(r)
+    } else {
+        op2(Op2Kind::Sub, l, r)
+    }
+}
+
+fn mul(l: Expr<f64>, r: Expr<f64>) -> Expr<f64> {
+    if is_num(&l, 0.0) || is_num(&r, 0.0) {
+        num(0.0)
+    } else if is_num(&l, 1.0) {
+        r
+    } else if is_num(&r, 1.0) {
+        l
+    } else {
+        match (l, r) {
+            // c1 * (c2 * e) = (c1 * c2) * e
+            (Expr::Value{v: a}, Expr::Op2{op: Op2Kind::Mul, l, r}) => match *l {
+                Expr::Value{v: b} => op2(Op2Kind::Mul, num(a * b), *r),
+                l => op2(Op2Kind::Mul, num(a), op2(Op2Kind::Mul, l, *r)),
+            },
+            (l, r) => op2(Op2Kind::Mul, l, r),
+        }
+    }
+}
+
+fn div(l: Expr<f64>, r: Expr<f64>) -> Expr<f64> {
+    if is_num(&l, 0.0) {
+        num(0.0)
+    } else {
+        op2(Op2Kind::Div, l, r)
+    }
+}
+
+fn call(name: &str, args: Vec<Expr<f64>>) -> Expr<f64> {
+    Expr::Call {name: name.to_string(), args}
+}
+
+impl Expr<f64> {
+    /// Whether the expression depends on variable `x`
+    fn has_var(&self, x: &str) -> bool {
+        match self {
+            Expr::Value{..} => false,
+            Expr::Var{name} => name == x,
+            Expr::Op1{child, ..} => child.has_var(x),
+            Expr::Op2{l, r, ..} => l.has_var(x) || r.has_var(x),
+            Expr::Call{args, ..} => args.iter().any(|a| a.has_var(x)),
+            Expr::Cond{c, t, f} => c.has_var(x) || t.has_var(x) || f.has_var(x),
+        }
+    }
+
+    /// Symbolic derivative by variable `x`, simplified
+    pub fn derivative(&self, x: &str) -> Result<Expr<f64>, EvalError> {
+        Ok(self.diff(x)?.simplify())
+    }
+
+    fn diff(&self, x: &str) -> Result<Expr<f64>, EvalError> {
+        if !self.has_var(x) {
+            return Ok(num(0.0));
+        }
+        let d = match self {
+            Expr::Value{..} => num(0.0),
+            Expr::Var{..} => num(1.0),
+            Expr::Op1{op: Op1Kind::Minus, child} => neg(child.diff(x)?),
+            Expr::Op2{op, l, r} => {
+                let (l, r) = (l.as_ref().clone(), r.as_ref().clone());
+                let (dl, dr) = (l.diff(x)?, r.diff(x)?);
+                match op {
+                    Op2Kind::Add => add(dl, dr),
+                    Op2Kind::Sub => sub(dl, dr),
+                    Op2Kind::Mul => add(mul(dl, r.clone()), mul(l, dr)),
+                    Op2Kind::Div => div(sub(mul(dl, r.clone()), mul(l, dr)), op2(Op2Kind::Pow, r, num(2.0))),
+                    Op2Kind::Pow => pow_rule(l, r, dl, dr),
+                    _ => return Err(EvalError::NotDifferentiable(op.symbol::<f64>().to_string())),
+                }
+            }
+            Expr::Call{name, args} => {
+                let args: Vec<Expr<f64>> = args.to_vec();
+                let da = args.iter().map(|a| a.diff(x)).collect::<Result<Vec<_>, _>>()?;
+                if name == "pow" && args.len() == 2 {
+                    pow_rule(args[0].clone(), args[1].clone(), da[0].clone(), da[1].clone())
+                } else if name == "atan2" && args.len() == 2 {
+                    // d atan2(y, x) = (x dy - y dx) / (x^2 + y^2)
+                    let (y, x) = (args[0].clone(), args[1].clone());
+                    let d = sub(mul(x.clone(), da[0].clone()), mul(y.clone(), da[1].clone()));
+                    div(d, add(op2(Op2Kind::Pow, x, num(2.0)), op2(Op2Kind::Pow, y, num(2.0))))
+                } else if args.len() == 1 {
+                    mul(func_derivative(name, args[0].clone())?, da[0].clone())
+                } else {
+                    return Err(EvalError::NotDifferentiable(name.clone()));
+                }
+            }
+            Expr::Cond{c, t, f} => Expr::Cond {c: c.clone(), t: Box::new(t.diff(x)?), f: Box::new(f.diff(x)?)},
+            Expr::Op1{op, ..} => return Err(EvalError::NotDifferentiable(op.symbol().to_string())),
+        };
+        Ok(d)
+    }
+}
+
+/// Derivative of `l ^ r`, where `dl` and `dr` are derivatives of `l` and `r`
+fn pow_rule(l: Expr<f64>, r: Expr<f64>, dl: Expr<f64>, dr: Expr<f64>) -> Expr<f64> {
+    if is_num(&dr, 0.0) {
+        // d l^n = n * l^(n-1) * dl
+        let p = op2(Op2Kind::Pow, l, op2(Op2Kind::Sub, r.clone(), num(1.0)));
+        mul(mul(r, p), dl)
+    } else {
+        // d l^r = l^r * (dr * ln(l) + r * dl / l)
+        let e = op2(Op2Kind::Pow, l.clone(), r.clone());
+        mul(e, add(mul(dr, call("ln", vec![l.clone()])), div(mul(r, dl), l)))
+    }
+}
+
+/// Derivative of built-in function `name` of one argument at `a`
+fn func_derivative(name: &str, a: Expr<f64>) -> Result<Expr<f64>, EvalError> {
+    let sq = |e: Expr<f64>| op2(Op2Kind::Pow, e, num(2.0));
+    let d = match name {
+        "abs" => div(a.clone(), call("abs", vec![a])),
+        "sqrt" => div(num(1.0), mul(num(2.0), call("sqrt", vec![a]))),
+        "exp" => call("exp", vec![a]),
+        "ln" => div(num(1.0), a),
+        "log10" => div(num(1.0), mul(a, call("ln", vec![num(10.0)]))),
+        "sin" => call("cos", vec![a]),
+        "cos" => neg(call("sin", vec![a])),
+        "tan" => div(num(1.0), sq(call("cos", vec![a]))),
+        "asin" => div(num(1.0), call("sqrt", vec![sub(num(1.0), sq(a))])),
+        "acos" => neg(div(num(1.0), call("sqrt", vec![sub(num(1.0), sq(a))]))),
+        "atan" => div(num(1.0), add(num(1.0), sq(a))),
+        _ => return Err(EvalError::NotDifferentiable(name.to_string())),
+    };
+    Ok(d)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse;
+
+    fn d(s: &str) -> String {
+        parse::<f64>(s).unwrap().derivative("x").map(|e| e.to_string()).unwrap_or_else(|e| e.to_string())
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(d("3"), "0");
+        assert_eq!(d("y"), "0");
+        assert_eq!(d("y * 2 + 1"), "0");
+        assert_eq!(d("x"), "1");
+    }
+
+    #[test]
+    fn product_and_quotient_rules() {
+        assert_eq!(d("x * sin(x)"), "sin(x) + x * cos(x)");
+        assert_eq!(d("x / (x + 1)"), "(x + 1 - x) / (x + 1) ^ 2");
+        assert_eq!(d("y / x"), "-y / x ^ 2");
+    }
+
+    #[test]
+    fn chain_rule() {
+        assert_eq!(d("sin(x ^ 2)"), "cos(x ^ 2) * (2 * x)");
+        assert_eq!(d("exp(2 * x)"), "exp(2 * x) * 2");
+        assert_eq!(d("ln(cos(x))"), "1 / cos(x) * -sin(x)");
+    }
+
+    #[test]
+    fn power_rule() {
+        assert_eq!(d("x ^ 3"), "3 * x ^ 2");
+        assert_eq!(d("pow(x, 2)"), "2 * x");
+        assert_eq!(d("x ^ y"), "y * x ^ (y - 1)");
+        assert_eq!(d("2 ^ x"), "2 ^ x * 0.6931471805599453");
+        assert_eq!(d("x ^ x"), "x ^ x * (ln(x) + x / x)");
+    }
+
+    #[test]
+    fn not_differentiable() {
+        assert_eq!(d("x % 2"), "cannot differentiate %");
+        assert_eq!(d("floor(x)"), "cannot differentiate floor");
+        assert_eq!(d("x > 0 ? x : -x"), "x > 0 ? 1 : -1");
+    }
+}
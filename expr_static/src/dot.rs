@@ -0,0 +1,171 @@
+use crate::TBound;
+use crate::expr::{Env, Expr, Op2Kind};
+use crate::ops::{call, find_func, EvalError, Value};
+
+/// Builder of a Graphviz digraph of an expression tree, which is evaluated along the way
+/// if there is an environment
+struct Dot<'a, T> {
+    env: Option<&'a Env<T>>,
+    /// Statements of the nodes, indexed by the nodes in preorder
+    nodes: Vec<String>,
+    edges: String,
+}
+
+/// Escapes `s` for a label in double quotes
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+impl<'a, T: TBound<T>> Dot<'a, T> {
+    fn graph(mut self, e: &Expr<T>) -> String {
+        self.node(e, self.env.is_some());
+        let mut s = String::from("digraph {\n  ordering=out;\n");
+        for n in &self.nodes {
+            s.push_str(n);
+        }
+        s.push_str(&self.edges);
+        s.push_str("}\n");
+        s
+    }
+
+    /// Adds the node of `e` and its subtree, evaluating them if `run`. Returns the value of `e`,
+    /// which is `None` if `e` was not evaluated or evaluation failed.
+    fn node(&mut self, e: &Expr<T>, run: bool) -> Option<Value<T>> {
+        let id = self.nodes.len();
+        self.nodes.push(String::new());
+        let (label, shape) = match e {
+            Expr::Value{v} => (v.to_literal(), ", shape=box"),
+            Expr::Var{name} => (name.clone(), ", shape=box, style=rounded"),
+            Expr::Op1{op, ..} => (op.to_string(), ""),
+            Expr::Op2{op, ..} => (op.symbol::<T>().to_string(), ""),
+            Expr::Call{name, ..} => (format!("{name}()"), ""),
+            Expr::Cond{..} => (String::from("?:"), ""),
+        };
+        // `None` if a subexpression has no value
+        let result = match e {
+            Expr::Value{v} => Some(Ok(Value::Val(v.clone()))),
+            Expr::Var{name} => {
+                let v = self.env.and_then(|env| env.get(name)).cloned();
+                Some(v.ok_or_else(|| EvalError::UnknownVariable(name.clone()).to_string()))
+            }
+            Expr::Op1{op, child} => {
+                let c = self.child(id, child, run);
+                c.map(|c| op.eval(c).map_err(|e| e.to_string()))
+            }
+            Expr::Op2{op: op @ (Op2Kind::And | Op2Kind::Or), l, r} => {
+                let l = self.child(id, l, run).map(|l| l.bool(op.symbol::<T>()));
+                let short = matches!(l, Some(Ok(b)) if b == matches!(op, Op2Kind::Or));
+                let r = self.child(id, r, run && matches!(l, Some(Ok(_))) && !short);
+                match l {
+                    Some(Ok(b)) if short => Some(Ok(Value::Bool(b))),
+                    Some(Ok(_)) => r.map(|r| r.bool(op.symbol::<T>()).map(Value::Bool).map_err(|e| e.to_string())),
+                    Some(Err(e)) => Some(Err(e.to_string())),
+                    None => None,
+                }
+            }
+            Expr::Op2{op, l, r} => {
+                let (l, r) = (self.child(id, l, run), self.child(id, r, run));
+                l.zip(r).map(|(l, r)| op.eval(l, r).map_err(|e| e.to_string()))
+            }
+            Expr::Call{name, args} => {
+                let vals: Vec<_> = args.iter().map(|a| self.child(id, a, run)).collect();
+                let vals: Option<Vec<_>> = vals.into_iter().collect();
+                // A call without arguments has no subexpression telling whether it is evaluated
+                vals.filter(|_| run).map(|vals| self.call(name, vals))
+            }
+            Expr::Cond{c, t, f} => {
+                let c = self.child(id, c, run).map(|c| c.bool("?:"));
+                let t = self.child(id, t, run && matches!(c, Some(Ok(true))));
+                let f = self.child(id, f, run && matches!(c, Some(Ok(false))));
+                match c {
+                    Some(Ok(_)) => t.or(f).map(Ok),
+                    Some(Err(e)) => Some(Err(e.to_string())),
+                    None => None,
+                }
+            }
+        };
+        let label = escape(&label);
+        let (node, v) = match result {
+            _ if self.env.is_none() => (format!("[label=\"{label}\"{shape}]"), None),
+            _ if !run => (format!("[label=\"{label}\\nnot evaluated\"{shape}, color=gray, fontcolor=gray]"), None),
+            Some(Ok(v)) => (format!("[label=\"{label}\\n= {}\"{shape}]", escape(&v.to_string())), Some(v)),
+            Some(Err(error)) => {
+                (format!("[label=\"{label}\\nerror: {}\"{shape}, color=red, fontcolor=red]", escape(&error)), None)
+            }
+            // The error is displayed at the subexpression
+            None => (format!("[label=\"{label}\"{shape}, color=red]"), None),
+        };
+        self.nodes[id] = format!("  n{id} {node};\n");
+        v
+    }
+
+    fn child(&mut self, parent: usize, e: &Expr<T>, run: bool) -> Option<Value<T>> {
+        self.edges.push_str(&format!("  n{parent} -> n{};\n", self.nodes.len()));
+        self.node(e, run)
+    }
+
+    /// Calls a built-in function, or a user function of the environment
+    fn call(&self, name: &str, vals: Vec<Value<T>>) -> Result<Value<T>, String> {
+        if find_func::<T>(name).is_none() {
+            let Some(env) = self.env else {
+                return Err(EvalError::UnknownFunction(name.to_string()).to_string());
+            };
+            // An error of the call itself is not located, as it is displayed at the call
+            return env.call(name, &vals, 0, 0, String::new).map_err(|e| match e.at.as_str() {
+                "" => e.error.to_string(),
+                _ => e.to_string(),
+            });
+        }
+        let mut args = Vec::with_capacity(vals.len());
+        for v in vals {
+            args.push(v.val("call").map_err(|e| e.to_string())?);
+        }
+        call(name, &args).map(Value::Val).map_err(|e| e.to_string())
+    }
+}
+
+impl<T: TBound<T>> Expr<T> {
+    /// Graphviz digraph of the expression tree, with a node labelled by each operator, value,
+    /// variable or call, and edges from operators to their operands in order
+    pub fn to_dot(&self) -> String {
+        Dot {env: None, nodes: Vec::new(), edges: String::new()}.graph(self)
+    }
+
+    /// Graphviz digraph like `to_dot`, with each node also labelled by its value with variables
+    /// of `env`, or by its error. Operands skipped by `&&`, `||` and `?:` are not evaluated.
+    pub fn to_dot_eval(&self, env: &Env<T>) -> String {
+        Dot {env: Some(env), nodes: Vec::new(), edges: String::new()}.graph(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expr::Env;
+    use crate::parser::{parse, parse_script};
+
+    fn env() -> Env<i32> {
+        let mut env = Env::default();
+        for stmt in parse_script("fn f() = 1; fn g(x) = x").unwrap() {
+            stmt.exec(&mut env).unwrap();
+        }
+        env
+    }
+
+    #[test]
+    fn call_without_arguments() {
+        let e = parse::<i32>("f() + g(f())").unwrap();
+        let dot = e.to_dot();
+        assert!(dot.contains("n1 [label=\"f()\"];"), "{dot}");
+        let dot = e.to_dot_eval(&env());
+        assert!(dot.contains("n1 [label=\"f()\\n= 1\"];"), "{dot}");
+        assert!(dot.contains("n0 [label=\"+\\n= 2\"];"), "{dot}");
+    }
+
+    #[test]
+    fn call_without_arguments_not_evaluated() {
+        let dot = parse::<i32>("1 == 1 || f() == 1").unwrap().to_dot_eval(&env());
+        assert!(dot.contains("n5 [label=\"f()\\nnot evaluated\", color=gray, fontcolor=gray];"), "{dot}");
+        let dot = parse::<i32>("h()").unwrap().to_dot_eval(&env());
+        assert!(dot.contains("error: unknown function h"), "{dot}");
+    }
+}
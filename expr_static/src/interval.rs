@@ -0,0 +1,508 @@
+use crate::ops::{EvalError, Func, Funcs, Ops};
+use crate::lexer::{float_literal, parse_float, TerminalEnd};
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// A closed interval of real numbers `[lo, hi]`. Results of operations are rounded outward,
+/// so they contain every value the operation yields for values from the operands.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+// Directed rounding: the exact result of an operation on `f64` values is the nearest result
+// plus its rounding error, which is computed exactly by `mul_add` or by the two-sum algorithm.
+
+/// Rounds `v` down if it is greater than the exact result `v + err`
+fn down(v: f64, err: f64) -> f64 {
+    if err < 0.0 { v.next_down() } else { v }
+}
+
+/// Rounds `v` up if it is less than the exact result `v + err`
+fn up(v: f64, err: f64) -> f64 {
+    if err > 0.0 { v.next_up() } else { v }
+}
+
+/// Rounding error of `a + b`
+fn add_err(a: f64, b: f64) -> f64 {
+    let s = a + b;
+    if !s.is_finite() {
+        return 0.0;
+    }
+    let bb = s - a;
+    (a - (s - bb)) + (b - bb)
+}
+
+/// Rounding error of `a * b`
+fn mul_err(a: f64, b: f64) -> f64 {
+    let p = a * b;
+    if p.is_finite() { a.mul_add(b, -p) } else { 0.0 }
+}
+
+/// Sign of the rounding error of `a / b`
+fn div_err(a: f64, b: f64) -> f64 {
+    let q = a / b;
+    if q == 0.0 && a != 0.0 {
+        // Underflow to zero
+        return a.signum() * b.signum();
+    }
+    if !q.is_finite() {
+        return 0.0;
+    }
+    // The exact quotient is `q + r / b`
+    let r = -q.mul_add(b, -a);
+    r * b.signum()
+}
+
+/// A bound of a product, in which zero times infinity is zero
+fn mul_bound(a: f64, b: f64, round: fn(f64, f64) -> f64) -> f64 {
+    if a == 0.0 || b == 0.0 { 0.0 } else { round(a * b, mul_err(a, b)) }
+}
+
+/// A bound of `x ^ n`, the upper one if `upper`
+fn powi_bound(x: f64, mut n: u64, upper: bool) -> f64 {
+    let neg = x < 0.0 && n % 2 == 1;
+    // The magnitude of a negative result is rounded in the opposite direction
+    let round = if upper != neg { up } else { down };
+    let (mut base, mut v) = (x.abs(), 1.0);
+    while n > 0 {
+        if n & 1 == 1 {
+            v = mul_bound(v, base, round);
+        }
+        n >>= 1;
+        if n > 0 {
+            base = mul_bound(base, base, round);
+        }
+    }
+    if neg { -v } else { v }
+}
+
+/// A nonnegative decimal number `0.d1d2... * 10^exp` by its significant digits without trailing
+/// zeros. Zero has no digits and the least exponent, so that ordering is by value.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Digits {
+    exp: i64,
+    digits: Vec<u8>,
+}
+
+impl Digits {
+    /// Digits of `int.frac * 10^exp`, where `int` and `frac` are strings of decimal digits
+    fn new(int: &str, frac: &str, exp: i64) -> Digits {
+        let mut digits: Vec<u8> = int.bytes().chain(frac.bytes()).map(|b| b - b'0').collect();
+        let lead = digits.iter().take_while(|&&d| d == 0).count();
+        digits.drain(..lead);
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        let exp = if digits.is_empty() { i64::MIN } else { int.len() as i64 - lead as i64 + exp };
+        Digits {exp, digits}
+    }
+
+    /// Exact digits of the magnitude of a finite `x`
+    fn of(x: f64) -> Digits {
+        // A finite `f64` has at most 1074 fractional digits
+        let s = format!("{:.1074}", x.abs());
+        let (int, frac) = s.split_once('.').unwrap_or((&s, ""));
+        Digits::new(int, frac, 0)
+    }
+
+    /// Digits of the magnitude of a decimal number like `-1.5e3`, `None` for other texts
+    fn parse(s: &str) -> Option<Digits> {
+        let s = s.replace('_', "");
+        let s = s.strip_prefix(['+', '-']).unwrap_or(&s);
+        let (m, e) = s.split_once(['e', 'E']).unwrap_or((s, "0"));
+        let (int, frac) = m.split_once('.').unwrap_or((m, ""));
+        let digits = |t: &str| t.bytes().all(|b| b.is_ascii_digit());
+        let e_digits = e.strip_prefix(['+', '-']).unwrap_or(e);
+        if int.is_empty() && frac.is_empty() || !digits(int) || !digits(frac) || e_digits.is_empty() || !digits(e_digits) {
+            return None;
+        }
+        // Exponents far beyond the range of `f64` are clamped
+        let exp = e_digits.parse::<i64>().unwrap_or(i64::MAX).min(100_000);
+        Some(Digits::new(int, frac, if e.starts_with('-') { -exp } else { exp }))
+    }
+
+    /// Rounds to `n` significant digits, away from zero if `away`, otherwise toward zero
+    fn round(&self, n: usize, away: bool) -> Digits {
+        if self.digits.len() <= n {
+            return self.clone();
+        }
+        let mut r = Digits {exp: self.exp, digits: self.digits[..n].to_vec()};
+        if away {
+            match r.digits.iter().rposition(|&d| d != 9) {
+                Some(i) => {
+                    r.digits[i] += 1;
+                    r.digits.truncate(i + 1);
+                }
+                None => r = Digits {exp: r.exp + 1, digits: vec![1]},
+            }
+        }
+        while r.digits.last() == Some(&0) {
+            r.digits.pop();
+        }
+        r
+    }
+
+    /// Decimal text of the number, negated if `neg`
+    fn text(&self, neg: bool) -> String {
+        let digits: String = self.digits.iter().map(|d| char::from(b'0' + d)).collect();
+        let n = digits.len() as i64;
+        let s = match self.exp {
+            i64::MIN => String::from("0"),
+            e if e <= 0 => format!("0.{}{digits}", "0".repeat(-e as usize)),
+            e if e >= n => format!("{digits}{}", "0".repeat((e - n) as usize)),
+            e => format!("{}.{}", &digits[..e as usize], &digits[e as usize..]),
+        };
+        if neg { format!("-{s}") } else { s }
+    }
+}
+
+/// The bounds of the real number of decimal text `s`: the nearest `f64` values below and above
+/// it, which are the same if `s` is exact
+fn bounds(s: &str) -> Option<(f64, f64)> {
+    let x = parse_float(s)?;
+    let Some(d) = Digits::parse(s) else {
+        // Infinity or NaN
+        return Some((x, x));
+    };
+    if x.is_infinite() {
+        // A finite number beyond the range of `f64`
+        return Some(if x > 0.0 { (f64::MAX, x) } else { (x, f64::MIN) });
+    }
+    Some(match (d.cmp(&Digits::of(x)), s.starts_with('-')) {
+        (Ordering::Equal, _) => (x, x),
+        (Ordering::Greater, false) | (Ordering::Less, true) => (x, x.next_up()),
+        _ => (x.next_down(), x),
+    })
+}
+
+/// Shortest text of the lower bound `x`, or the upper bound if `upper`, which parses back to
+/// `x` as that bound, like `0.1` for the `f64` value below 0.1
+fn bound_text(x: f64, upper: bool) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+    // A number from `x` up to the next value outward parses back to `x`, rounding digits
+    // toward that value finds the shortest one within 17 digits
+    let bound = |s: &str| bounds(s).map(|(lo, hi)| if upper { hi } else { lo });
+    let d = Digits::of(x);
+    let away = upper == (x < 0.0);
+    (1..=17)
+        .map(|n| d.round(n, away).text(x < 0.0))
+        .find(|t| bound(t) == Some(x))
+        .unwrap_or_else(|| d.text(x < 0.0))
+}
+
+/// Applies `f` to the pairs of bounds of `a` and `b`
+fn corners(a: &Interval, b: &Interval, f: impl Fn(f64, f64) -> f64) -> [f64; 4] {
+    [f(a.lo, b.lo), f(a.lo, b.hi), f(a.hi, b.lo), f(a.hi, b.hi)]
+}
+
+fn min4(v: [f64; 4]) -> f64 {
+    v.into_iter().fold(f64::INFINITY, f64::min)
+}
+
+fn max4(v: [f64; 4]) -> f64 {
+    v.into_iter().fold(f64::NEG_INFINITY, f64::max)
+}
+
+impl Interval {
+    fn new(lo: f64, hi: f64) -> Result<Interval, EvalError> {
+        if lo.is_nan() || hi.is_nan() || lo > hi {
+            return Err(EvalError::InvalidArgument("interval"));
+        }
+        Ok(Interval {lo, hi})
+    }
+
+    fn point(v: f64) -> Interval {
+        Interval {lo: v, hi: v}
+    }
+
+    /// An interval from bounds computed by functions that are not correctly rounded,
+    /// widened by a unit in the last place on each side
+    fn widen(lo: f64, hi: f64) -> Result<Interval, EvalError> {
+        Interval::new(lo.next_down(), hi.next_up())
+    }
+
+    fn contains_zero(&self) -> bool {
+        self.lo <= 0.0 && 0.0 <= self.hi
+    }
+
+    fn abs(self) -> Interval {
+        if self.lo >= 0.0 {
+            self
+        } else if self.hi <= 0.0 {
+            Interval {lo: -self.hi, hi: -self.lo}
+        } else {
+            Interval {lo: 0.0, hi: (-self.lo).max(self.hi)}
+        }
+    }
+
+    fn sqrt(self) -> Result<Interval, EvalError> {
+        if self.lo < 0.0 {
+            return Err(EvalError::InvalidArgument("sqrt"));
+        }
+        let bound = |x: f64, round: fn(f64, f64) -> f64| {
+            let s = x.sqrt();
+            if s.is_finite() { round(s, -s.mul_add(s, -x)) } else { s }
+        };
+        Interval::new(bound(self.lo, down), bound(self.hi, up))
+    }
+
+    fn powi(self, n: u64) -> Interval {
+        let base = if n.is_multiple_of(2) { self.abs() } else { self };
+        Interval {lo: powi_bound(base.lo, n, false), hi: powi_bound(base.hi, n, true)}
+    }
+}
+
+/// Displays a point as a number, other intervals as `[lo, hi]`
+impl Display for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        if self.lo == self.hi {
+            write!(f, "{}", self.lo)
+        } else {
+            write!(f, "[{}, {}]", self.lo, self.hi)
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = ();
+    /// Parses bounds `[1.0, 1.1]`, a center and a radius `1.05±0.05`, or a point `1.05`.
+    /// Numbers without an exact `f64` value are rounded outward.
+    fn from_str(s: &str) -> Result<Interval, ()> {
+        let num = |s: &str| bounds(s.trim()).ok_or(());
+        if let Some(b) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            let (lo, hi) = b.split_once(',').ok_or(())?;
+            return Interval::new(num(lo)?.0, num(hi)?.1).map_err(|_| ());
+        }
+        let (lo, hi) = match s.split_once('±') {
+            Some((c, r)) => {
+                let ((clo, chi), (rlo, r)) = (num(c)?, num(r)?);
+                if rlo < 0.0 {
+                    return Err(());
+                }
+                (down(clo - r, add_err(clo, -r)), up(chi + r, add_err(chi, r)))
+            }
+            None => num(s)?,
+        };
+        Interval::new(lo, hi).map_err(|_| ())
+    }
+}
+
+impl Ops<Interval> for Interval {
+    const TYPE: &'static str = "interval";
+    fn zero() -> Option<Interval> {
+        Some(Interval::point(0.0))
+    }
+    fn one() -> Option<Interval> {
+        Some(Interval::point(1.0))
+    }
+    fn neg_involutive() -> bool {
+        true
+    }
+    fn signed_zero() -> bool {
+        true
+    }
+    fn neg(&self) -> Result<Interval, EvalError> {
+        Ok(Interval {lo: -self.hi, hi: -self.lo})
+    }
+    fn add(&self, r: &Interval) -> Result<Interval, EvalError> {
+        Interval::new(down(self.lo + r.lo, add_err(self.lo, r.lo)), up(self.hi + r.hi, add_err(self.hi, r.hi)))
+    }
+    fn sub(&self, r: &Interval) -> Result<Interval, EvalError> {
+        self.add(&r.neg()?)
+    }
+    fn mul(&self, r: &Interval) -> Result<Interval, EvalError> {
+        let lo = min4(corners(self, r, |a, b| mul_bound(a, b, down)));
+        let hi = max4(corners(self, r, |a, b| mul_bound(a, b, up)));
+        Interval::new(lo, hi)
+    }
+    /// Division by an interval containing zero is an error, as the result would be unbounded
+    fn div(&self, r: &Interval) -> Result<Interval, EvalError> {
+        if r.contains_zero() {
+            return Err(EvalError::DivisionByZero);
+        }
+        let lo = min4(corners(self, r, |a, b| down(a / b, div_err(a, b))));
+        let hi = max4(corners(self, r, |a, b| up(a / b, div_err(a, b))));
+        Interval::new(lo, hi)
+    }
+    fn rem(&self, _r: &Interval) -> Result<Interval, EvalError> {
+        Err(Self::unsupported("%"))
+    }
+    /// Power with an integer exponent, or with any exponent for a positive base
+    fn pow(&self, r: &Interval) -> Result<Interval, EvalError> {
+        if r.lo == r.hi && r.lo.fract() == 0.0 && r.lo.abs() <= u32::MAX as f64 {
+            let p = self.powi(r.lo.abs() as u64);
+            return if r.lo < 0.0 { Interval::point(1.0).div(&p) } else { Ok(p) };
+        }
+        if !(self.lo > 0.0 || self.lo == 0.0 && r.lo > 0.0) {
+            return Err(EvalError::InvalidArgument("^"));
+        }
+        // `x ^ y` is monotone in each argument for positive `x`
+        let v = corners(self, r, f64::powf);
+        Interval::widen(min4(v), max4(v))
+    }
+    /// Intervals are ordered only if they do not overlap, or if they are the same point
+    fn compare(&self, r: &Interval) -> Result<Option<Ordering>, EvalError> {
+        Ok(if self.hi < r.lo {
+            Some(Ordering::Less)
+        } else if self.lo > r.hi {
+            Some(Ordering::Greater)
+        } else if self.lo == self.hi && r.lo == r.hi && self.lo == r.lo {
+            Some(Ordering::Equal)
+        } else {
+            None
+        })
+    }
+    /// Intervals are equal only if they are the same point, and different only if they do not
+    /// overlap, so that each comparison holds for all values
+    fn equal(&self, r: &Interval) -> Result<Option<bool>, EvalError> {
+        Ok(self.compare(r)?.map(|o| o == Ordering::Equal))
+    }
+}
+
+impl Funcs for Interval {
+    const FUNCS: &'static [Func<Interval>] = &[
+        Func {name: "lo", arity: 1, f: |a| Ok(Interval::point(a[0].lo))},
+        Func {name: "hi", arity: 1, f: |a| Ok(Interval::point(a[0].hi))},
+        Func {name: "mid", arity: 1, f: |a| Ok(Interval::point(a[0].lo / 2.0 + a[0].hi / 2.0))},
+        Func {name: "width", arity: 1, f: |a| Ok(Interval::point(up(a[0].hi - a[0].lo, add_err(a[0].hi, -a[0].lo))))},
+        Func {name: "abs", arity: 1, f: |a| Ok(a[0].abs())},
+        Func {name: "min", arity: 2, f: |a| Interval::new(a[0].lo.min(a[1].lo), a[0].hi.min(a[1].hi))},
+        Func {name: "max", arity: 2, f: |a| Interval::new(a[0].lo.max(a[1].lo), a[0].hi.max(a[1].hi))},
+        Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        Func {name: "sqrt", arity: 1, f: |a| a[0].sqrt()},
+        Func {name: "exp", arity: 1, f: |a| Interval::new(a[0].lo.exp().next_down().max(0.0), a[0].hi.exp().next_up())},
+        Func {name: "ln", arity: 1, f: |a| if a[0].lo < 0.0 {
+            Err(EvalError::InvalidArgument("ln"))
+        } else {
+            Interval::widen(a[0].lo.ln(), a[0].hi.ln())
+        }},
+    ];
+}
+
+impl TerminalEnd for Interval {
+    fn pattern(c: char) -> bool {
+        !(c.is_ascii_digit() || c == '.')
+    }
+    /// Text of the bounds, or of a point, which parses back to the same bounds
+    fn to_literal(&self) -> String {
+        if self.lo == self.hi && bounds(&self.lo.to_string()) == Some((self.lo, self.lo)) {
+            self.lo.to_string()
+        } else if self.lo == self.hi && self.lo.is_finite() {
+            Digits::of(self.lo).text(self.lo < 0.0)
+        } else {
+            format!("[{}, {}]", bound_text(self.lo, false), bound_text(self.hi, true))
+        }
+    }
+    /// A literal is bounds in brackets, or a number optionally followed by `±` and a radius
+    fn literal(s: &str) -> usize {
+        if s.starts_with('[') {
+            return s.find(']').map_or(s.len(), |i| i + 1);
+        }
+        let n = float_literal(s);
+        match s[n..].strip_prefix('±') {
+            Some(r) if n > 0 => s.len() - r.len() + float_literal(r),
+            _ => n,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(s: &str) -> Interval {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn directed_rounding() {
+        assert_eq!(down(1.0, -1e-20), 1.0f64.next_down());
+        assert_eq!(down(1.0, 1e-20), 1.0);
+        assert_eq!(up(1.0, 1e-20), 1.0f64.next_up());
+        assert_eq!(up(1.0, -1e-20), 1.0);
+        assert_eq!(up(1.0, 0.0), 1.0);
+    }
+
+    #[test]
+    fn rounding_errors() {
+        // 0.1 + 0.2 is above the sum of the two `f64` values
+        assert!(add_err(0.1, 0.2) < 0.0);
+        assert_eq!(add_err(1.0, 2.0), 0.0);
+        assert_eq!(add_err(1e16, 1.0), 1.0);
+        assert_eq!(add_err(f64::MAX, f64::MAX), 0.0);
+        // (2^53 - 1) * 3 takes 55 bits, the error is exact in integers
+        let (a, b) = (9007199254740991.0, 3.0);
+        assert_eq!((a * b) as i128 + mul_err(a, b) as i128, 27021597764222973);
+        assert_eq!(mul_err(1.5, 2.0), 0.0);
+        // 1/3 rounds down, 1/10 rounds up
+        assert!(div_err(1.0, 3.0) > 0.0);
+        assert!(div_err(1.0, 10.0) < 0.0);
+        assert!(div_err(-1.0, 3.0) < 0.0);
+        assert_eq!(div_err(1.0, 4.0), 0.0);
+        assert!(div_err(f64::MIN_POSITIVE, 1e300) > 0.0);
+    }
+
+    #[test]
+    fn bounds_of_products_and_powers() {
+        assert_eq!(mul_bound(0.0, f64::INFINITY, down), 0.0);
+        // 0.1 * 3 is between 0.3 and the next `f64` value
+        assert_eq!(mul_bound(0.1, 3.0, down), 0.3);
+        assert_eq!(mul_bound(0.1, 3.0, up), 0.3f64.next_up());
+        // 10^23 is between the `f64` values around it
+        let (lo, hi) = (powi_bound(10.0, 23, false), powi_bound(10.0, 23, true));
+        assert!((lo as i128) < 10i128.pow(23) && 10i128.pow(23) < hi as i128);
+        assert_eq!(hi, lo.next_up());
+        assert_eq!(powi_bound(-2.0, 3, false), -8.0);
+        let (lo, hi) = (powi_bound(-10.0, 23, false), powi_bound(-10.0, 23, true));
+        assert!((lo as i128) < -10i128.pow(23) && -10i128.pow(23) < hi as i128);
+    }
+
+    #[test]
+    fn literals_round_outward() {
+        let i = interval("[0.1, 0.3]");
+        assert_eq!((i.lo, i.hi), (0.1f64.next_down(), 0.3f64.next_up()));
+        let i = interval("0.1");
+        assert_eq!((i.lo, i.hi), (0.1f64.next_down(), 0.1));
+        let i = interval("-0.1");
+        assert_eq!((i.lo, i.hi), (-0.1, -0.1f64.next_down()));
+        let i = interval("0.5");
+        assert_eq!((i.lo, i.hi), (0.5, 0.5));
+        let i = interval("[1, 1e400]");
+        assert_eq!((i.lo, i.hi), (1.0, f64::INFINITY));
+        let i = interval("1e-400");
+        assert_eq!((i.lo, i.hi), (0.0, f64::from_bits(1)));
+        let i = interval("1.05±0.05");
+        assert!(i.lo < 1.0 && 1.1 <= i.hi);
+        let i = interval("1±0.5");
+        assert_eq!((i.lo, i.hi), (0.5, 1.5));
+    }
+
+    #[test]
+    fn literal_text() {
+        for s in ["[0.1, 0.3]", "0.5", "[-0.3, -0.1]", "[1, inf]"] {
+            assert_eq!(interval(s).to_literal(), s);
+        }
+        for i in [interval("1.05±0.05"), Interval::point(0.1), Interval {lo: 1.0 / 3.0, hi: 2.0 / 3.0}] {
+            assert!(interval(&i.to_literal()) == i, "{}", i.to_literal());
+        }
+    }
+
+    #[test]
+    fn equality_of_all_values() {
+        let r = |s: &str| crate::tests::eval::<Interval>(s);
+        assert_eq!(r("[1, 2] == [1, 2]"), Ok(String::from("false")));
+        assert_eq!(r("[1, 2] != [1, 2]"), Ok(String::from("false")));
+        assert_eq!(r("[1, 2] != [2, 3]"), Ok(String::from("false")));
+        assert_eq!(r("[1, 2] != [3, 4]"), Ok(String::from("true")));
+        assert_eq!(r("[1, 2] == [3, 4]"), Ok(String::from("false")));
+        assert_eq!(r("0.5 == [0.5, 0.5]"), Ok(String::from("true")));
+        assert_eq!(r("0.5 != 0.25"), Ok(String::from("true")));
+        assert_eq!(r("0.1 == 0.1"), Ok(String::from("false")));
+        assert_eq!(r("(1 < 2) == (2 < 1)"), Ok(String::from("false")));
+    }
+}
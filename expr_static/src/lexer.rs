@@ -0,0 +1,349 @@
+use crate::TBound;
+use crate::expr::OPERATORS;
+use crate::parser::ParseError;
+use std::fmt::Display;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// Punctuation, the symbols of operators are in `OPERATORS`
+const PUNCTUATION: &[&str] = &["(", ")", ",", ";", "=", "?", ":"];
+
+#[derive(Clone)]
+pub enum TokenKind<'a, T> {
+    Value(T),
+    /// A variable, function or keyword
+    Ident(&'a str),
+    /// An operator from `OPERATORS` or punctuation
+    Symbol(&'static str),
+    /// A character that does not begin any token
+    Invalid,
+    End,
+}
+
+#[derive(Clone)]
+pub struct Token<'a, T> {
+    pub kind: TokenKind<'a, T>,
+    /// Text of the token in the input
+    pub text: &'a str,
+    /// Byte offset in the input
+    pub pos: usize,
+    /// Character offset in the input
+    pub col: usize,
+}
+
+pub fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+pub fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Splits `input` into tokens ending with `TokenKind::End`. Values are recognized by
+/// `TerminalEnd` of `T`, and tokens are separated by any Unicode whitespace.
+pub fn tokenize<T: TBound<T>>(input: &str) -> Result<Vec<Token<'_, T>>, ParseError> {
+    let input = input.trim_end();
+    let mut tokens = Vec::new();
+    let (mut pos, mut col) = (0, 0);
+    loop {
+        let rest = input[pos..].trim_start();
+        col += input[pos..input.len() - rest.len()].chars().count();
+        pos = input.len() - rest.len();
+        let Some(c) = rest.chars().next() else {
+            tokens.push(Token {kind: TokenKind::End, text: rest, pos, col});
+            return Ok(tokens);
+        };
+        let (kind, n) = token::<T>(rest, c).ok_or_else(|| ParseError {
+            pos,
+            col,
+            expected: "value",
+            found: format!("'{}'", &rest[..T::literal(rest)]),
+        })?;
+        let text = &rest[..n];
+        tokens.push(Token {kind, text, pos, col});
+        pos += n;
+        col += text.chars().count();
+    }
+}
+
+/// The token at the start of `rest`, which begins with `c`, and its length in bytes,
+/// or `None` for a malformed value
+fn token<T: TBound<T>>(rest: &str, c: char) -> Option<(TokenKind<'_, T>, usize)> {
+    if is_ident_start(c) {
+        let n = rest.find(|c| !is_ident(c)).unwrap_or(rest.len());
+        // A name before `(` is a function, others may be values of some types, like `inf` or `km`
+        if rest[n..].trim_start().starts_with('(') || T::literal(rest) == 0 {
+            return Some((TokenKind::Ident(&rest[..n]), n));
+        }
+    }
+    // A result of an earlier statement `$1` or `$_` is a variable
+    if let Some(r) = rest.strip_prefix('$') {
+        let n = if r.starts_with('_') { 1 } else { r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len()) };
+        if n > 0 {
+            return Some((TokenKind::Ident(&rest[..1 + n]), 1 + n));
+        }
+    }
+    let n = T::literal(rest);
+    if n > 0 {
+        return T::parse_literal(&rest[..n]).map(|v| (TokenKind::Value(v), n));
+    }
+    // The longest symbol, so `<=` is not `<` followed by `=`
+    let symbol = OPERATORS.iter().map(|d| d.symbol).filter(|s| !s.starts_with(is_ident_start))
+        .chain(PUNCTUATION.iter().copied())
+        .filter(|s| rest.starts_with(s))
+        .max_by_key(|s| s.len());
+    match symbol {
+        Some(s) => Some((TokenKind::Symbol(s), s.len())),
+        None => Some((TokenKind::Invalid, c.len_utf8())),
+    }
+}
+
+/// Length of the digits in `radix` at the start of `s`, which may be separated by single `_`
+fn digits(s: &str, radix: u32) -> usize {
+    let mut n = 0;
+    let mut separated = false;
+    for (i, c) in s.char_indices() {
+        if c.is_digit(radix) {
+            n = i + 1;
+            separated = false;
+        } else if c == '_' && n > 0 && !separated {
+            separated = true;
+        } else {
+            break;
+        }
+    }
+    n
+}
+
+/// Radix of an integer literal with prefix `0x`, `0o` or `0b`, and its digits
+fn radix(s: &str) -> (u32, &str) {
+    match s.get(..2) {
+        Some("0x" | "0X") => (16, &s[2..]),
+        Some("0o" | "0O") => (8, &s[2..]),
+        Some("0b" | "0B") => (2, &s[2..]),
+        _ => (10, s),
+    }
+}
+
+/// Length of an integer literal at the start of `s`: decimal digits, or hexadecimal, octal
+/// or binary digits after a prefix `0x`, `0o` or `0b`, with `_` separators like `1_000`
+pub fn int_literal(s: &str) -> usize {
+    match radix(s) {
+        (10, _) => digits(s, 10),
+        (r, d) => match digits(d, r) {
+            0 => 1,
+            n => 2 + n,
+        },
+    }
+}
+
+/// Value of an integer literal recognized by `int_literal`
+pub fn parse_int<T>(s: &str, from_str_radix: fn(&str, u32) -> Result<T, ParseIntError>) -> Option<T> {
+    let (r, d) = radix(s);
+    from_str_radix(&d.replace('_', ""), r).ok()
+}
+
+/// Length of a floating-point literal at the start of `s`: digits with `_` separators, an
+/// optional fraction and an optional exponent like `1.5e-3`, or a word `inf` or `nan`
+pub fn float_literal(s: &str) -> usize {
+    for w in ["inf", "nan", "NaN"] {
+        match s.strip_prefix(w) {
+            Some(r) if !r.starts_with(is_ident) => return w.len(),
+            _ => {}
+        }
+    }
+    let mut n = digits(s, 10);
+    if let Some(f) = s[n..].strip_prefix('.') {
+        n += 1 + digits(f, 10);
+    }
+    if let Some(e) = s[n..].strip_prefix(['e', 'E']).filter(|_| n > 0) {
+        let sign = usize::from(e.starts_with(['+', '-']));
+        match digits(&e[sign..], 10) {
+            0 => {}
+            d => n += 1 + sign + d,
+        }
+    }
+    n
+}
+
+/// Value of a floating-point literal recognized by `float_literal`, possibly with a sign
+pub fn parse_float(s: &str) -> Option<f64> {
+    s.replace('_', "").parse().ok()
+}
+
+pub trait TerminalEnd {
+    fn pattern(c: char) -> bool;
+    /// Length in bytes of the literal value at the start of `s`, by default up to
+    /// the first character matching `pattern`
+    fn literal(s: &str) -> usize {
+        s.find(Self::pattern).unwrap_or(s.len())
+    }
+    /// Value of literal `s`
+    fn parse_literal(s: &str) -> Option<Self> where Self: FromStr {
+        s.parse().ok()
+    }
+    /// Text of a literal with value `self`, which parses back to an equal value
+    fn to_literal(&self) -> String where Self: Display {
+        self.to_string()
+    }
+}
+fn pattern_int(c: char) -> bool {
+    c.is_ascii_digit()
+}
+impl TerminalEnd for i32 {
+    fn pattern(c: char) -> bool {
+        !pattern_int(c)
+    }
+    fn literal(s: &str) -> usize {
+        int_literal(s)
+    }
+    fn parse_literal(s: &str) -> Option<i32> {
+        parse_int(s, i32::from_str_radix)
+    }
+}
+impl TerminalEnd for u32 {
+    fn pattern(c: char) -> bool {
+        !pattern_int(c)
+    }
+    fn literal(s: &str) -> usize {
+        int_literal(s)
+    }
+    fn parse_literal(s: &str) -> Option<u32> {
+        parse_int(s, u32::from_str_radix)
+    }
+}
+impl TerminalEnd for f64 {
+    fn pattern(c: char) -> bool {
+        !(pattern_int(c) || c == '.')
+    }
+    fn literal(s: &str) -> usize {
+        float_literal(s)
+    }
+    fn parse_literal(s: &str) -> Option<f64> {
+        parse_float(s)
+    }
+}
+/// A string literal is enclosed in double quotes, with escape sequences `\"`, `\\`, `\n`,
+/// `\r`, `\t`, `\0` and `\u{HEX}`. Digits without quotes are a string of the digits.
+impl TerminalEnd for String {
+    fn pattern(c: char) -> bool {
+        !c.is_ascii_digit()
+    }
+    fn literal(s: &str) -> usize {
+        let Some(q) = s.strip_prefix('"') else {
+            return s.find(Self::pattern).unwrap_or(s.len());
+        };
+        let mut escaped = false;
+        for (i, c) in q.char_indices() {
+            match c {
+                '"' if !escaped => return i + 2,
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        s.len()
+    }
+    fn parse_literal(s: &str) -> Option<String> {
+        let Some(q) = s.strip_prefix('"') else {
+            return Some(s.to_string());
+        };
+        let mut v = String::new();
+        let mut chars = q.strip_suffix('"')?.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                v.push(c);
+                continue;
+            }
+            v.push(match chars.next()? {
+                c @ ('"' | '\\') => c,
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    let hex = chars.as_str().strip_prefix('{')?;
+                    let (hex, rest) = hex.split_once('}')?;
+                    let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+                    chars = rest.chars();
+                    c
+                }
+                _ => return None,
+            });
+        }
+        Some(v)
+    }
+    fn to_literal(&self) -> String {
+        if !self.is_empty() && self.bytes().all(|c| c.is_ascii_digit()) {
+            return self.clone();
+        }
+        let mut s = String::from('"');
+        for c in self.chars() {
+            match c {
+                '"' | '\\' => {
+                    s.push('\\');
+                    s.push(c);
+                }
+                '\n' => s.push_str("\\n"),
+                '\r' => s.push_str("\\r"),
+                '\t' => s.push_str("\\t"),
+                '\0' => s.push_str("\\0"),
+                c if c.is_control() => s.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => s.push(c),
+            }
+        }
+        s.push('"');
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tokenize, TokenKind};
+    use crate::tests::eval;
+
+    fn ok(s: &str) -> Result<String, String> {
+        Ok(String::from(s))
+    }
+
+    fn found(s: &str, col: usize) -> Result<String, String> {
+        Err(format!("expected operator, ';' or end of input, found '{s}' at character {col} (byte {col})"))
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(eval::<i32>("0x1f + 0XF"), ok("46"));
+        assert_eq!(eval::<i32>("0o17 + 0b101"), ok("20"));
+        assert_eq!(eval::<u32>("0xffff_ffff"), ok("4294967295"));
+        assert_eq!(eval::<i32>("1_000_000"), ok("1000000"));
+        assert_eq!(eval::<i32>("0x"), found("x", 1));
+        assert_eq!(eval::<i32>("0b1012"), found("2", 5));
+        assert_eq!(eval::<i32>("1__0"), found("__0", 1));
+        assert_eq!(eval::<i32>("1_"), found("_", 1));
+        assert_eq!(eval::<i32>("1e3"), found("e3", 1));
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(eval::<f64>("1e3"), ok("1000"));
+        assert_eq!(eval::<f64>("1.5e-3"), ok("0.0015"));
+        assert_eq!(eval::<f64>("2E+2"), ok("200"));
+        assert_eq!(eval::<f64>(".5 + 5."), ok("5.5"));
+        assert_eq!(eval::<f64>("1_000.000_1"), ok("1000.0001"));
+        assert_eq!(eval::<f64>("-inf"), ok("-inf"));
+        assert_eq!(eval::<f64>("nan"), ok("NaN"));
+        assert_eq!(eval::<f64>("infinity"), Err(String::from("unknown variable infinity")));
+        assert_eq!(eval::<f64>("1e"), found("e", 1));
+        assert_eq!(eval::<f64>("1e_3"), found("e_3", 1));
+        assert_eq!(eval::<f64>("1__0"), found("__0", 1));
+        assert_eq!(eval::<f64>("1.2.3"), found(".3", 3));
+    }
+
+    #[test]
+    fn unicode_whitespace() {
+        assert_eq!(eval::<i32>("1\u{a0}+\u{2003}2"), ok("3"));
+        assert_eq!(eval::<f64>("\u{3000}1 *\u{2028}3\u{85}"), ok("3"));
+        let tokens = tokenize::<String>("\"\u{e9}\"\u{a0}+ x").unwrap();
+        let at: Vec<_> = tokens.iter().map(|t| (t.text, t.pos, t.col)).collect();
+        assert_eq!(at, [("\"\u{e9}\"", 0, 0), ("+", 6, 4), ("x", 8, 6), ("", 9, 7)]);
+        assert!(matches!(tokens[1].kind, TokenKind::Symbol("+")));
+    }
+}
@@ -1,208 +1,1280 @@
-use parser::TerminalEnd;
+use lexer::TerminalEnd;
 use std::env;
-use std::io;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
 use std::process::{ExitCode, Termination};
 use ops::*;
+use expr::{Env, Expr, ExprError};
 use std::fmt::Display;
+use std::hint::black_box;
 use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
+
+mod bigint;
+mod complex;
+mod decimal;
+mod deriv;
+mod dot;
+mod interval;
+mod lexer;
+mod quantity;
+mod rational;
+mod repl;
+mod serial;
+mod vm;
+
+/// Settings from command line arguments
+#[derive(Default)]
+struct Options {
+    /// Initial values of variables
+    vars: Vec<(String, String)>,
+    /// Display expressions with all subexpressions in parentheses
+    full_parens: bool,
+    /// Differentiate by this variable instead of evaluating
+    diff: Option<String>,
+    /// Evaluate by the stack machine instead of the tree walking interpreter
+    vm: bool,
+    /// Number of evaluations by each method for benchmarking
+    bench: Option<u32>,
+    /// Read statements from this file instead of stdin
+    script: Option<String>,
+    /// Representation of expressions read
+    input: serial::Repr,
+    /// Representation of expressions displayed
+    output: serial::Repr,
+    /// Display statements as Graphviz digraphs of their expressions instead
+    dot: bool,
+    /// Label nodes of the digraphs also by their values
+    dot_eval: bool,
+}
+
+/// Stack size of the thread evaluating statements: 8 KiB for each of `expr::MAX_NESTING` levels
+/// of evaluation, which take up to 5 KiB in unoptimized builds, and 8 MiB for everything else.
+/// Memory of the stack is only reserved until it is used.
+const STACK_SIZE: usize = (expr::MAX_NESTING << 13) + (8 << 20);
 
 fn main() -> impl Termination {
+    // Settings are thread local, so arguments are also processed by the new thread
+    match thread::Builder::new().stack_size(STACK_SIZE).spawn(start) {
+        Ok(t) => t.join().unwrap_or(ExitCode::FAILURE),
+        Err(error) => {
+            println!("Cannot start evaluation: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn start() -> ExitCode {
     let mut argv = env::args();
     let argv0 = argv.next().unwrap();
-    if argv.len() != 1 {
-        return usage(&argv0);
+    let mut t: Option<String> = None;
+    let mut opts = Options::default();
+    for arg in argv {
+        if let Some(a) = arg.strip_prefix("--arith=") {
+            match a.parse::<Arith>() {
+                Ok(a) => set_arith(a),
+                Err(_) => return usage(&argv0),
+            }
+        } else if let Some(n) = arg.strip_prefix("--scale=") {
+            match n.parse::<u32>() {
+                Ok(n) if n <= decimal::MAX_SCALE => decimal::set_scale(n),
+                _ => return usage(&argv0),
+            }
+        } else if let Some(r) = arg.strip_prefix("--rounding=") {
+            match r.parse::<decimal::Rounding>() {
+                Ok(r) => decimal::set_rounding(r),
+                Err(_) => return usage(&argv0),
+            }
+        } else if let Some(style) = arg.strip_prefix("--style=") {
+            match style {
+                "min" => opts.full_parens = false,
+                "full" => opts.full_parens = true,
+                _ => return usage(&argv0),
+            }
+        } else if arg == "--dot" {
+            opts.dot = true;
+        } else if arg == "--dot=eval" {
+            opts.dot = true;
+            opts.dot_eval = true;
+        } else if arg == "--vm" {
+            opts.vm = true;
+        } else if let Some(n) = arg.strip_prefix("--bench=") {
+            match n.parse::<u32>() {
+                Ok(n) if n > 0 => opts.bench = Some(n),
+                _ => return usage(&argv0),
+            }
+        } else if let Some(n) = arg.strip_prefix("--max-depth=") {
+            match n.parse::<usize>() {
+                Ok(n) if n <= expr::MAX_DEPTH_LIMIT => expr::set_max_depth(n),
+                _ => return usage(&argv0),
+            }
+        } else if let Some(r) = arg.strip_prefix("--input=") {
+            match r.parse::<serial::Repr>() {
+                Ok(r) => opts.input = r,
+                Err(_) => return usage(&argv0),
+            }
+        } else if let Some(r) = arg.strip_prefix("--output=") {
+            match r.parse::<serial::Repr>() {
+                Ok(r) => opts.output = r,
+                Err(_) => return usage(&argv0),
+            }
+        } else if let Some(path) = arg.strip_prefix("--script=") {
+            opts.script = Some(path.to_string());
+        } else if let Some(x) = arg.strip_prefix("--diff=") {
+            opts.diff = Some(x.to_string());
+        } else if let Some((name, value)) = arg.split_once('=') {
+            if !is_var_name(name) {
+                return usage(&argv0);
+            }
+            opts.vars.push((name.to_string(), value.to_string()));
+        } else if t.is_none() {
+            t = Some(arg);
+        } else {
+            return usage(&argv0);
+        }
     }
-    let argv1 = argv.next().unwrap();
-    match argv1.as_str() {
-        "i" => run::<i32>(),
-        "u" => run::<u32>(),
-        "d" => run::<f64>(),
-        "s" => run::<String>(),
+    let Some(t) = t else {
+        return usage(&argv0);
+    };
+    match (t.as_str(), &opts.diff) {
+        ("i", None) => run::<i32>(&opts),
+        ("u", None) => run::<u32>(&opts),
+        ("d", None) => run::<f64>(&opts),
+        ("d", Some(x)) => run_diff(x, &opts),
+        ("s", None) => run::<String>(&opts),
+        ("n", None) => run::<bigint::BigInt>(&opts),
+        ("q", None) => run::<rational::Rational>(&opts),
+        ("c", None) => run::<complex::Complex>(&opts),
+        ("m", None) => run::<decimal::Decimal>(&opts),
+        ("r", None) => run::<interval::Interval>(&opts),
+        ("p", None) => run::<quantity::Quantity>(&opts),
         _ => usage(&argv0),
     }
 }
 
+/// Whether `name` of a NAME=VALUE argument is an identifier, which the lexer reads as a name
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(lexer::is_ident_start) && chars.all(lexer::is_ident)
+}
+
 fn usage(argv0: &str) -> ExitCode {
-    eprintln!("usage: {} {}", argv0, "{i|u|d|s}
+    eprintln!("usage: {argv0} [--arith=checked|wrapping|saturating] [--style=min|full]
+    [--scale=N] [--rounding=half-even|half-up|truncate]
+    [--vm] [--bench=N] [--max-depth=N] [--script=FILE]
+    [--input=infix|json|sexpr] [--output=infix|json|sexpr] [--dot[=eval]]
+    {{i|u|d|s|n|q|c|m|r|p}} [NAME=VALUE...]
+       {argv0} [--style=min|full] --diff=VAR d [NAME=VALUE...]
 
-Evaluates an expression read from stdin consisting of:
+Evaluates lines of statements separated by ';', read interactively if stdin is
+a terminal, otherwise from stdin or from FILE (--script). An error is reported
+for its line, and the following lines are still evaluated. A statement is
+'let NAME = EXPR', which assigns a value to a variable until the end of input,
+'fn NAME(PARAM, ...) = EXPR', which defines a function until the end of input,
+or an expression EXPR consisting of:
 - values
-- unary operator -
-- binary operators +, -, *, /
+- variables (names starting with a letter or '_')
+- results of earlier statements $1, $2, ... and the last one $_
+- calls of built-in and defined functions NAME(ARG, ...)
+- unary operators -, ! (logical not), ~ (bitwise not, only for i and u)
+- postfix factorial ! (only for i, u and n)
+- binary operators +, -, *, /, % (remainder)
+- power ^ or ** (right associative, binds tighter than unary operators; only **
+  for i and u, where ^ is the exclusive or)
+- bitwise operators & (and), | (or), ^ (exclusive or), shifts << and >> (only
+  for i and u, at the precedence levels of C, so & binds weaker than ==)
+- comparisons ==, !=, <, <=, >, >=
+- logical operators && (and), || (or), evaluated left to right with short-circuit
+- conditional COND ? EXPR1 : EXPR2
+- conversion EXPR in UNIT (only for p, binds weakest)
 - parentheses
-- whitespace (ignored)
+- whitespace, including Unicode spaces (ignored)
 
 Selection of type:
 
-i = i32
-u = u32
-d = f64
-s = str (only binary +, whitespace needed around operators and parentheses)
+i = i32 (values like 42, 0xff, 0o17, 0b1010 or 1_000)
+u = u32 (values like i32 values)
+d = f64 (values like 1.5, 1_000.5, 1.5e-3, inf or nan)
+s = str (values like \"a b\" with escapes \\\" \\\\ \\n \\r \\t \\0 \\u{{HEX}}, digits
+    alone are a string of digits; + concatenates, - removes all occurrences,
+    * repeats a string a number of times)
+n = arbitrary precision integer (a power with more than 2^20 bits, estimated as
+    the bits of the base times the exponent, or a factorial beyond 10000! overflows)
+q = exact rational number (values like 0.25, / divides exactly, so 3/4 is three
+    quarters)
+c = complex number with f64 parts (imaginary values like 4i or i, == and !=
+    compare both parts, no <, <=, >, >= or %)
+m = decimal fixed-point number with N fractional digits (--scale, default 4,
+    at most 18) and a magnitude below 1.7e38 / 10^N
+r = interval of f64 values (values like [1.0, 1.1] or 1.05±0.05, rounded
+    outward to f64 bounds, comparisons are true only if they hold for all
+    values, no %)
+p = physical quantity of f64 values and units (values like 3 m, 2.5 km/h or
+    9.81 m/s^2, a unit alone like km is one unit, so units cannot be used as
+    variable names; units m, km, cm, mm, g, kg, t, s, ms, min, h, A, K, mol,
+    cd, L, Hz, N, Pa, J, W, C, V)
+
+Built-in functions:
+
+i, u, n = abs(x), min(x, y), max(x, y), pow(x, y)
+d = abs(x), min(x, y), max(x, y), pow(x, y), sqrt(x), exp(x), ln(x), log10(x),
+    sin(x), cos(x), tan(x), asin(x), acos(x), atan(x), atan2(y, x),
+    floor(x), ceil(x), round(x)
+s = len(x), upper(x), lower(x), trim(x)
+q = abs(x), min(x, y), max(x, y), pow(x, y), num(x), den(x), floor(x), ceil(x)
+c = abs(x), arg(x), conj(x), re(x), im(x), pow(x, y), sqrt(x), exp(x), ln(x)
+m = abs(x), min(x, y), max(x, y), pow(x, y), floor(x), ceil(x), round(x)
+r = lo(x), hi(x), mid(x), width(x), abs(x), min(x, y), max(x, y), pow(x, y),
+    sqrt(x), exp(x), ln(x)
+p = abs(x), min(x, y), max(x, y), pow(x, y), sqrt(x)
+
+Integer arithmetic (i, u) on overflow:
+
+checked = fails with an error (default)
+wrapping = wraps around
+saturating = stops at the minimum or maximum value
+
+Decimal arithmetic (m) on results of * and / or values with more fractional
+digits than the scale:
+
+half-even = rounds to the nearest value, ties to an even last digit (default)
+half-up = rounds to the nearest value, ties away from zero
+truncate = rounds toward zero
+
+Initial values of variables can be set by NAME=VALUE arguments, where VALUE is
+an expression.
+
+Expressions are displayed with the minimal number of parentheses (min, default),
+or with each subexpression in parentheses (full).
+
+Expressions can also be read (--input) and displayed (--output) as syntax trees,
+in JSON or as S-expressions instead of infix text (infix, default). A line of
+input is then a single expression, without 'let' or 'fn', so pretty-printed
+JSON spanning several lines cannot be read, but the single lines displayed by
+--output can. Objects and arrays of JSON, and lists of S-expressions, are nested
+at most 50000 levels deep. In JSON, a node is
+an object {{\"value\":\"LITERAL\"}}, {{\"var\":\"NAME\"}}, {{\"call\":\"NAME\",\"args\":[...]}},
+{{\"op\":\"cond\",\"c\":...,\"t\":...,\"f\":...}}, {{\"op\":\"OP\",\"child\":...}} for a unary
+operator or {{\"op\":\"OP\",\"l\":...,\"r\":...}} for a binary operator, with OP one of
+  neg not bitnot fact add sub mul div rem pow bitand bitor bitxor shl shr
+  eq ne lt le gt ge and or in
+An S-expression is (OP ARG...) with the symbol of an operator or the name of a
+function, like (+ 1 (* 2 (f x))), (- x) for negation, (x !) for factorial and
+(?: c t f) for a conditional. A literal that is not a single atom, like -1 or
+3 m, is written in double quotes, like (+ \"-1\" \"3 m\").
+
+With --dot, each statement is displayed only as a Graphviz digraph of the tree
+of its expression, or of the body of a function definition, and errors as DOT
+comments. With --dot=eval, each node is also labelled by its value or error,
+or as not evaluated if skipped by &&, || or ?:.
+
+Each statement is also displayed after simplification, which evaluates constant
+subexpressions and applies algebraic identities, like x * 1 = x, and x + 0 = x
+only for types without a negative zero (not d, c and r). Identities do not apply
+to comparisons, which have boolean values, and do not remove constant
+subexpressions that fail, like 1 / 0 in 1 / 0 * 0.
+
+Statements are evaluated by walking the expression tree, or by compiling the
+expression to bytecode of a stack machine (--vm). With --bench=N, each expression
+is evaluated N times by each method and the times are displayed.
+
+The body of a defined function sees its parameters and variables set before
+the call, not the parameters of the calling function. A function can call
+itself and functions defined later, up to N nested calls (--max-depth, default
+1000, at most 10000) and up to 50000 nested subexpressions and calls in total.
+Built-in functions cannot be redefined.
+
+With --diff=VAR, a single expression is read and its derivative by variable VAR
+is displayed and evaluated (only for d).
+
+An invalid expression, or one nested more than 50000 levels deep, is reported
+with the position of the error.
+
+A line can also be a command:
+
+:type [EXPR] = displays the type of values, or of the value of EXPR
+:ast EXPR = displays the syntax tree of EXPR
+:vars = displays variables and their values
+:help = lists commands
 ");
     ExitCode::FAILURE
 }
 
-pub trait TBound<T>: 'static + Clone + Default + PartialEq + Display + FromStr + Ops<T> + TerminalEnd {}
+pub trait TBound<T>: 'static + Clone + Default + PartialEq + Display + FromStr + Ops<T> + Funcs + TerminalEnd {}
 impl<T> TBound<T> for T where
-    T: 'static + Clone + Default + PartialEq + Display + FromStr + Ops<T> + TerminalEnd {}
+    T: 'static + Clone + Default + PartialEq + Display + FromStr + Ops<T> + Funcs + TerminalEnd {}
+
+/// Creates the environment with variables set from command line arguments
+fn init_env<T: TBound<T>>(opts: &Options) -> Option<Env<T>> {
+    let mut env = Env::<T>::default();
+    for (name, value) in &opts.vars {
+        match parser::parse::<T>(value) {
+            Ok(e) => match e.eval_with(&env) {
+                Ok(v) => env.set(name, v),
+                Err(error) => {
+                    println!("Cannot evaluate variable {name}: {error}");
+                    return None;
+                }
+            },
+            Err(error) => {
+                println!("Invalid value of variable {name}: {error}");
+                return None;
+            }
+        }
+    }
+    Some(env)
+}
 
-fn run<T: TBound<T>>() -> ExitCode {
+fn read_input() -> Option<String> {
     let mut input = String::new();
     match io::stdin().read_line(&mut input) {
-        Ok(_) => {}
+        Ok(_) => Some(input),
         Err(error) => {
+            println!("Cannot read expression: {error}");
+            None
+        }
+    }
+}
+
+fn run<T: TBound<T>>(opts: &Options) -> ExitCode {
+    let Some(env) = init_env::<T>(opts) else {
+        return ExitCode::FAILURE;
+    };
+    let mut session = repl::Session::new(env, opts);
+    if let Some(path) = &opts.script {
+        match fs::read_to_string(path) {
+            Ok(input) => session.script(&input, true),
+            Err(error) => {
+                println!("Cannot read script {path}: {error}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if io::stdin().is_terminal() {
+        session.repl();
+    } else {
+        let mut input = String::new();
+        if let Err(error) = io::stdin().read_to_string(&mut input) {
             println!("Cannot read expression: {error}");
             return ExitCode::FAILURE;
         }
+        session.script(&input, false);
     }
-    if let Some(e) = parser::parse::<T>(&input) {
-        println!("{}", e);
-        if let Some(v) = e.eval() {
-            println!("{}", v);
-        } else {
-            println!("no value");
+    ExitCode::SUCCESS
+}
+
+/// Compares the speed of evaluation by the tree walking interpreter and by the stack machine
+fn bench<T: TBound<T>>(e: &Expr<T>, env: &Env<T>, n: u32) {
+    fn show<T: Display>(r: &Result<Value<T>, ExprError>) -> String {
+        match r {
+            Ok(v) => v.to_string(),
+            Err(error) => format!("no value: {error}"),
         }
+    }
+    let start = Instant::now();
+    let mut tree = e.eval_with(env);
+    for _ in 1..n {
+        tree = black_box(e.eval_with(black_box(env)));
+    }
+    let t_tree = start.elapsed();
+    let start = Instant::now();
+    let p = vm::Program::compile(e);
+    let vars = p.bind(env);
+    let t_compile = start.elapsed();
+    let start = Instant::now();
+    let mut vm = p.run(&vars, env);
+    for _ in 1..n {
+        vm = black_box(p.run(black_box(&vars), env));
+    }
+    let t_vm = start.elapsed();
+    println!("bench: {n} evaluations, tree {t_tree:?}, vm {t_vm:?} + compile {t_compile:?}");
+    let (tree, vm) = (show(&tree), show(&vm));
+    if tree != vm {
+        println!("bench: different results, tree {tree}, vm {vm}");
+    }
+}
+
+fn run_diff(x: &str, opts: &Options) -> ExitCode {
+    let Some(env) = init_env::<f64>(opts) else {
+        return ExitCode::FAILURE;
+    };
+    let Some(input) = read_input() else {
+        return ExitCode::FAILURE;
+    };
+    let e = match parser::parse::<f64>(&input) {
+        Ok(e) => e,
+        Err(error) => {
+            println!("{}", error.caret(&input));
+            println!("invalid expression: {error}");
+            return ExitCode::SUCCESS;
+        }
+    };
+    if opts.full_parens {
+        println!("{e:#}");
     } else {
-        println!("invalid expression");
+        println!("{e}");
+    }
+    match e.derivative(x) {
+        Ok(d) => {
+            if opts.full_parens {
+                println!("d/d{x}: {d:#}");
+            } else {
+                println!("d/d{x}: {d}");
+            }
+            match d.eval_with(&env) {
+                Ok(v) => println!("{}", v),
+                Err(error) => println!("no value: {error}"),
+            }
+        }
+        Err(error) => println!("no derivative: {error}"),
     }
     ExitCode::SUCCESS
 }
 
 mod ops {
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::fmt::{Display, Error, Formatter};
+    use std::str::FromStr;
+
+    /// Reason why an operation cannot produce a value
+    #[derive(Debug)]
+    pub enum EvalError {
+        DivisionByZero,
+        Overflow,
+        UnknownVariable(String),
+        UnknownFunction(String),
+        ArgCount {
+            func: String,
+            expected: usize,
+            found: usize,
+        },
+        InvalidArgument(&'static str),
+        TypeMismatch(&'static str),
+        NotDifferentiable(String),
+        Unsupported {
+            op: &'static str,
+            ty: &'static str,
+        },
+        /// An operand of `op` is not an integer
+        ExpectedInteger {
+            op: &'static str,
+            found: String,
+        },
+        /// Operands of `op` are physical quantities of different dimensions
+        IncompatibleDimensions {
+            op: &'static str,
+            l: String,
+            r: String,
+        },
+        /// Calls of user functions are nested deeper than the limit
+        RecursionLimit(usize),
+        /// Evaluation is nested deeper than `expr::MAX_NESTING`
+        NestingLimit(usize),
+        /// A user function has the name of a built-in function
+        Redefined(String),
+    }
+    impl Display for EvalError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            match self {
+                EvalError::DivisionByZero => write!(f, "division by zero"),
+                EvalError::Overflow => write!(f, "arithmetic overflow"),
+                EvalError::UnknownVariable(name) => write!(f, "unknown variable {name}"),
+                EvalError::UnknownFunction(name) => write!(f, "unknown function {name}"),
+                EvalError::ArgCount{func, expected, found} =>
+                    write!(f, "function {func} expects {expected} arguments, got {found}"),
+                EvalError::InvalidArgument(func) => write!(f, "invalid argument of {func}"),
+                EvalError::TypeMismatch(op) => write!(f, "operands of {op} have different types"),
+                EvalError::NotDifferentiable(op) => write!(f, "cannot differentiate {op}"),
+                EvalError::Unsupported{op, ty} => write!(f, "operator {op} not supported for type {ty}"),
+                EvalError::ExpectedInteger{op, found} => write!(f, "operand of {op} must be an integer, found {found:?}"),
+                EvalError::IncompatibleDimensions{op, l, r} =>
+                    write!(f, "operands of {op} have incompatible dimensions {l} and {r}"),
+                EvalError::RecursionLimit(n) => write!(f, "more than {n} nested calls of user functions"),
+                EvalError::NestingLimit(n) => write!(f, "more than {n} nested subexpressions in evaluation"),
+                EvalError::Redefined(name) => write!(f, "cannot redefine built-in function {name}"),
+            }
+        }
+    }
+
+    /// A result of evaluation, either a value of the selected type, or a result of a comparison
+    #[derive(Clone, PartialEq)]
+    pub enum Value<T> {
+        Val(T),
+        Bool(bool),
+    }
+    impl<T: Ops<T>> Value<T> {
+        /// Gets the value of the selected type, for use as an operand of `op`
+        pub fn val(self, op: &'static str) -> Result<T, EvalError> {
+            match self {
+                Value::Val(v) => Ok(v),
+                Value::Bool(_) => Err(EvalError::Unsupported {op, ty: "bool"}),
+            }
+        }
+        /// Gets the boolean value, for use as an operand of `op`
+        pub fn bool(self, op: &'static str) -> Result<bool, EvalError> {
+            match self {
+                Value::Val(_) => Err(T::unsupported(op)),
+                Value::Bool(b) => Ok(b),
+            }
+        }
+    }
+    impl<T: Display> Display for Value<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            match self {
+                Value::Val(v) => write!(f, "{v}"),
+                Value::Bool(b) => write!(f, "{b}"),
+            }
+        }
+    }
+
+    /// Behavior of integer operations when the result does not fit into the type
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Arith {
+        Checked,
+        Wrapping,
+        Saturating,
+    }
+    impl FromStr for Arith {
+        type Err = ();
+        fn from_str(s: &str) -> Result<Arith, ()> {
+            match s {
+                "checked" => Ok(Arith::Checked),
+                "wrapping" => Ok(Arith::Wrapping),
+                "saturating" => Ok(Arith::Saturating),
+                _ => Err(()),
+            }
+        }
+    }
+
+    thread_local! {
+        static ARITH: Cell<Arith> = const { Cell::new(Arith::Checked) };
+    }
+    pub fn set_arith(a: Arith) {
+        ARITH.with(|c| c.set(a));
+    }
+    pub fn arith() -> Arith {
+        ARITH.with(|c| c.get())
+    }
+
+    /// Selects the result of an integer operation according to the current `Arith` policy
+    fn int_result<T>(checked: Option<T>, wrapping: T, saturating: T) -> Result<T, EvalError> {
+        match arith() {
+            Arith::Checked => checked.ok_or(EvalError::Overflow),
+            Arith::Wrapping => Ok(wrapping),
+            Arith::Saturating => Ok(saturating),
+        }
+    }
+
     pub trait Ops<T> {
-        fn neg(&self) -> Option<T>;
-        fn add(&self, r: &T) -> Option<T>;
-        fn sub(&self, r: &T) -> Option<T>;
-        fn mul(&self, r: &T) -> Option<T>;
-        fn div(&self, r: &T) -> Option<T>;
+        /// Name of the type used in error messages
+        const TYPE: &'static str;
+        fn neg(&self) -> Result<T, EvalError>;
+        fn add(&self, r: &T) -> Result<T, EvalError>;
+        fn sub(&self, r: &T) -> Result<T, EvalError>;
+        fn mul(&self, r: &T) -> Result<T, EvalError>;
+        fn div(&self, r: &T) -> Result<T, EvalError>;
+        fn rem(&self, r: &T) -> Result<T, EvalError>;
+        fn pow(&self, r: &T) -> Result<T, EvalError>;
+        /// Ordering of values, `None` if they are unordered
+        fn compare(&self, r: &T) -> Result<Option<Ordering>, EvalError>;
+        /// Whether values are equal for `==`, or different for `!=`, `None` if neither holds
+        fn equal(&self, r: &T) -> Result<Option<bool>, EvalError> where Self: PartialEq<T> {
+            Ok(Some(self == r))
+        }
+        /// The value expressed in the unit of `r`, for `x in UNIT`
+        fn convert(&self, _r: &T) -> Result<T, EvalError> {
+            Err(Self::unsupported("in"))
+        }
+        /// Bitwise and `&`
+        fn bitand(&self, _r: &T) -> Result<T, EvalError> {
+            Err(Self::unsupported("&"))
+        }
+        /// Bitwise or `|`
+        fn bitor(&self, _r: &T) -> Result<T, EvalError> {
+            Err(Self::unsupported("|"))
+        }
+        /// Bitwise exclusive or, binary `^` in types with bitwise operators
+        fn bitxor(&self, _r: &T) -> Result<T, EvalError> {
+            Err(Self::unsupported("^"))
+        }
+        /// Bitwise complement, unary `~`
+        fn bitnot(&self) -> Result<T, EvalError> {
+            Err(Self::unsupported("unary ~"))
+        }
+        /// Factorial, postfix `!`
+        fn fact(&self) -> Result<T, EvalError> {
+            Err(Self::unsupported("!"))
+        }
+        /// Shift left `<<` by `r` bits
+        fn shl(&self, _r: &T) -> Result<T, EvalError> {
+            Err(Self::unsupported("<<"))
+        }
+        /// Shift right `>>` by `r` bits
+        fn shr(&self, _r: &T) -> Result<T, EvalError> {
+            Err(Self::unsupported(">>"))
+        }
+        fn unsupported(op: &'static str) -> EvalError {
+            EvalError::Unsupported {op, ty: Self::TYPE}
+        }
+        /// Whether the type has bitwise operators, where `^` is the exclusive or and `**` the
+        /// power, like in C
+        fn bitwise() -> bool {
+            false
+        }
+        /// Value `0` such that `x - 0 == x`, and `x + 0 == 0 + x == x` unless `signed_zero`
+        fn zero() -> Option<T> {
+            None
+        }
+        /// Value `1` such that `x * 1 == 1 * x == x / 1 == x ^ 1 == x` and `x ^ 0 == 1`
+        fn one() -> Option<T> {
+            None
+        }
+        /// Whether `x * 0 == 0 * x == x - x == 0` holds for every value `x`
+        fn annihilates() -> bool {
+            false
+        }
+        /// Whether `-(-x) == x` holds for every value `x`
+        fn neg_involutive() -> bool {
+            false
+        }
+        /// Whether the type has a negative zero like IEEE 754, so `-0 + 0` is `0`, not `-0`
+        fn signed_zero() -> bool {
+            false
+        }
     }
     impl Ops<i32> for i32 {
-        fn neg(&self) -> Option<i32> {
-            Some(-self)
+        const TYPE: &'static str = "i32";
+        fn zero() -> Option<i32> {
+            Some(0)
+        }
+        fn one() -> Option<i32> {
+            Some(1)
+        }
+        fn annihilates() -> bool {
+            true
+        }
+        fn bitwise() -> bool {
+            true
+        }
+        fn neg_involutive() -> bool {
+            arith() == Arith::Wrapping
         }
-        fn add(&self, r: &i32) -> Option<i32> {
-            Some(self + *r)
+        fn neg(&self) -> Result<i32, EvalError> {
+            int_result(self.checked_neg(), self.wrapping_neg(), self.saturating_neg())
+        }
+        fn add(&self, r: &i32) -> Result<i32, EvalError> {
+            int_result(self.checked_add(*r), self.wrapping_add(*r), self.saturating_add(*r))
+        }
+        fn sub(&self, r: &i32) -> Result<i32, EvalError> {
+            int_result(self.checked_sub(*r), self.wrapping_sub(*r), self.saturating_sub(*r))
+        }
+        fn mul(&self, r: &i32) -> Result<i32, EvalError> {
+            int_result(self.checked_mul(*r), self.wrapping_mul(*r), self.saturating_mul(*r))
+        }
+        fn compare(&self, r: &i32) -> Result<Option<Ordering>, EvalError> {
+            Ok(self.partial_cmp(r))
+        }
+        fn div(&self, r: &i32) -> Result<i32, EvalError> {
+            if *r == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            int_result(self.checked_div(*r), self.wrapping_div(*r), self.saturating_div(*r))
+        }
+        fn rem(&self, r: &i32) -> Result<i32, EvalError> {
+            if *r == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            Ok(self.wrapping_rem(*r))
         }
-        fn sub(&self, r: &i32) -> Option<i32> {
-            Some(self - *r)
+        fn pow(&self, r: &i32) -> Result<i32, EvalError> {
+            let Ok(e) = u32::try_from(*r) else {
+                return Err(EvalError::InvalidArgument("**"));
+            };
+            int_result(self.checked_pow(e), self.wrapping_pow(e), self.saturating_pow(e))
         }
-        fn mul(&self, r: &i32) -> Option<i32> {
-            Some(self * *r)
+        fn bitand(&self, r: &i32) -> Result<i32, EvalError> {
+            Ok(self & r)
         }
-        fn div(&self, r: &i32) -> Option<i32> {
-            Some(self / *r)
+        fn bitor(&self, r: &i32) -> Result<i32, EvalError> {
+            Ok(self | r)
+        }
+        fn bitxor(&self, r: &i32) -> Result<i32, EvalError> {
+            Ok(self ^ r)
+        }
+        fn bitnot(&self) -> Result<i32, EvalError> {
+            Ok(!self)
+        }
+        /// Shift by a negative amount or by at least the bit width is an error
+        fn shl(&self, r: &i32) -> Result<i32, EvalError> {
+            u32::try_from(*r).ok().and_then(|n| self.checked_shl(n)).ok_or(EvalError::InvalidArgument("<<"))
+        }
+        /// Arithmetic shift, which fills the vacated bits with the sign bit
+        fn shr(&self, r: &i32) -> Result<i32, EvalError> {
+            u32::try_from(*r).ok().and_then(|n| self.checked_shr(n)).ok_or(EvalError::InvalidArgument(">>"))
+        }
+        /// Factorial of a non-negative integer
+        fn fact(&self) -> Result<i32, EvalError> {
+            if *self < 0 {
+                return Err(EvalError::InvalidArgument("!"));
+            }
+            let mut v: i32 = 1;
+            for k in 2..=*self {
+                // A wrapped product reaches 0 and a saturated one `MAX`, then it does not change
+                if v == 0 || v == i32::MAX {
+                    break;
+                }
+                v = v.mul(&k)?;
+            }
+            Ok(v)
         }
     }
     impl Ops<u32> for u32 {
-        fn neg(&self) -> Option<u32> {
-            None
+        const TYPE: &'static str = "u32";
+        fn zero() -> Option<u32> {
+            Some(0)
+        }
+        fn one() -> Option<u32> {
+            Some(1)
+        }
+        fn annihilates() -> bool {
+            true
+        }
+        fn bitwise() -> bool {
+            true
+        }
+        fn neg(&self) -> Result<u32, EvalError> {
+            Err(Self::unsupported("unary -"))
+        }
+        fn add(&self, r: &u32) -> Result<u32, EvalError> {
+            int_result(self.checked_add(*r), self.wrapping_add(*r), self.saturating_add(*r))
+        }
+        fn sub(&self, r: &u32) -> Result<u32, EvalError> {
+            int_result(self.checked_sub(*r), self.wrapping_sub(*r), self.saturating_sub(*r))
         }
-        fn add(&self, r: &u32) -> Option<u32> {
-            Some(self + *r)
+        fn mul(&self, r: &u32) -> Result<u32, EvalError> {
+            int_result(self.checked_mul(*r), self.wrapping_mul(*r), self.saturating_mul(*r))
         }
-        fn sub(&self, r: &u32) -> Option<u32> {
-            Some(self - *r)
+        fn compare(&self, r: &u32) -> Result<Option<Ordering>, EvalError> {
+            Ok(self.partial_cmp(r))
         }
-        fn mul(&self, r: &u32) -> Option<u32> {
-            Some(self * *r)
+        fn div(&self, r: &u32) -> Result<u32, EvalError> {
+            if *r == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            Ok(self / *r)
+        }
+        fn rem(&self, r: &u32) -> Result<u32, EvalError> {
+            if *r == 0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            Ok(self % *r)
+        }
+        fn pow(&self, r: &u32) -> Result<u32, EvalError> {
+            int_result(self.checked_pow(*r), self.wrapping_pow(*r), self.saturating_pow(*r))
+        }
+        fn bitand(&self, r: &u32) -> Result<u32, EvalError> {
+            Ok(self & r)
+        }
+        fn bitor(&self, r: &u32) -> Result<u32, EvalError> {
+            Ok(self | r)
+        }
+        fn bitxor(&self, r: &u32) -> Result<u32, EvalError> {
+            Ok(self ^ r)
+        }
+        fn bitnot(&self) -> Result<u32, EvalError> {
+            Ok(!self)
         }
-        fn div(&self, r: &u32) -> Option<u32> {
-            Some(self / *r)
+        /// Shift by at least the bit width is an error
+        fn shl(&self, r: &u32) -> Result<u32, EvalError> {
+            self.checked_shl(*r).ok_or(EvalError::InvalidArgument("<<"))
+        }
+        fn shr(&self, r: &u32) -> Result<u32, EvalError> {
+            self.checked_shr(*r).ok_or(EvalError::InvalidArgument(">>"))
+        }
+        fn fact(&self) -> Result<u32, EvalError> {
+            let mut v: u32 = 1;
+            for k in 2..=*self {
+                // A wrapped product reaches 0 and a saturated one `MAX`, then it does not change
+                if v == 0 || v == u32::MAX {
+                    break;
+                }
+                v = v.mul(&k)?;
+            }
+            Ok(v)
         }
     }
     impl Ops<f64> for f64 {
-        fn neg(&self) -> Option<f64> {
-            Some(-self)
+        const TYPE: &'static str = "f64";
+        fn zero() -> Option<f64> {
+            Some(0.0)
+        }
+        fn one() -> Option<f64> {
+            Some(1.0)
+        }
+        fn neg_involutive() -> bool {
+            true
+        }
+        fn signed_zero() -> bool {
+            true
+        }
+        fn neg(&self) -> Result<f64, EvalError> {
+            Ok(-self)
         }
-        fn add(&self, r: &f64) -> Option<f64> {
-            Some(self + *r)
+        fn add(&self, r: &f64) -> Result<f64, EvalError> {
+            Ok(self + *r)
         }
-        fn sub(&self, r: &f64) -> Option<f64> {
-            Some(self - *r)
+        fn sub(&self, r: &f64) -> Result<f64, EvalError> {
+            Ok(self - *r)
         }
-        fn mul(&self, r: &f64) -> Option<f64> {
-            Some(self * *r)
+        fn mul(&self, r: &f64) -> Result<f64, EvalError> {
+            Ok(self * *r)
         }
-        fn div(&self, r: &f64) -> Option<f64> {
-            Some(self / *r)
+        fn compare(&self, r: &f64) -> Result<Option<Ordering>, EvalError> {
+            Ok(self.partial_cmp(r))
+        }
+        fn div(&self, r: &f64) -> Result<f64, EvalError> {
+            if *r == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            Ok(self / *r)
+        }
+        fn rem(&self, r: &f64) -> Result<f64, EvalError> {
+            if *r == 0.0 {
+                return Err(EvalError::DivisionByZero);
+            }
+            Ok(self % *r)
+        }
+        fn pow(&self, r: &f64) -> Result<f64, EvalError> {
+            real("^", &[*self, *r], self.powf(*r))
         }
     }
+    /// Limit of the length in bytes of a string built by repetition
+    const MAX_STR_LEN: usize = 1 << 24;
+
     impl Ops<String> for String {
-        fn neg(&self) -> Option<String> {
-            None
+        const TYPE: &'static str = "str";
+        fn zero() -> Option<String> {
+            Some(String::new())
         }
-        fn add(&self, r: &String) -> Option<String> {
-            Some((self).clone() + &*r)
+        fn neg(&self) -> Result<String, EvalError> {
+            Err(Self::unsupported("unary -"))
         }
-        fn sub(&self, _: &String) -> Option<String> {
-            None
+        fn add(&self, r: &String) -> Result<String, EvalError> {
+            Ok(self.clone() + r)
         }
-        fn mul(&self, _: &String) -> Option<String> {
-            None
+        /// Removes all occurrences of `r`
+        fn sub(&self, r: &String) -> Result<String, EvalError> {
+            if r.is_empty() {
+                return Ok(self.clone());
+            }
+            Ok(self.replace(r.as_str(), ""))
         }
-        fn div(&self, _: &String) -> Option<String> {
-            None
+        /// Repeats a string a number of times given by the other operand
+        fn mul(&self, r: &String) -> Result<String, EvalError> {
+            let count = |s: &String| s.parse::<usize>().ok().filter(|_| s.bytes().all(|c| c.is_ascii_digit()));
+            let (s, n) = match (count(r), count(self)) {
+                (Some(n), _) => (self, n),
+                (None, Some(n)) => (r, n),
+                (None, None) => return Err(EvalError::ExpectedInteger {op: "*", found: r.clone()}),
+            };
+            if s.len().checked_mul(n).is_none_or(|len| len > MAX_STR_LEN) {
+                return Err(EvalError::Overflow);
+            }
+            Ok(s.repeat(n))
+        }
+        fn compare(&self, r: &String) -> Result<Option<Ordering>, EvalError> {
+            Ok(self.partial_cmp(r))
+        }
+        fn div(&self, _: &String) -> Result<String, EvalError> {
+            Err(Self::unsupported("/"))
+        }
+        fn rem(&self, _: &String) -> Result<String, EvalError> {
+            Err(Self::unsupported("%"))
         }
+        fn pow(&self, _: &String) -> Result<String, EvalError> {
+            Err(Self::unsupported("^"))
+        }
+    }
+
+    /// A built-in function callable from expressions
+    pub struct Func<T> {
+        pub name: &'static str,
+        pub arity: usize,
+        pub f: fn(&[T]) -> Result<T, EvalError>,
+    }
+
+    /// Table of built-in functions available for a type
+    pub trait Funcs: 'static + Sized {
+        const FUNCS: &'static [Func<Self>] = &[];
+    }
+
+    impl<T> Func<T> {
+        pub fn call(&self, args: &[T]) -> Result<T, EvalError> {
+            if args.len() != self.arity {
+                return Err(EvalError::ArgCount {func: self.name.to_string(), expected: self.arity, found: args.len()});
+            }
+            (self.f)(args)
+        }
+    }
+
+    /// Finds built-in function `name` of type `T`
+    pub fn find_func<T: Funcs>(name: &str) -> Option<&'static Func<T>> {
+        T::FUNCS.iter().find(|f| f.name == name)
+    }
+
+    /// Calls built-in function `name` of type `T`
+    pub fn call<T: Funcs>(name: &str, args: &[T]) -> Result<T, EvalError> {
+        match find_func::<T>(name) {
+            Some(func) => func.call(args),
+            None => Err(EvalError::UnknownFunction(name.to_string())),
+        }
+    }
+
+    impl Funcs for i32 {
+        const FUNCS: &'static [Func<i32>] = &[
+            Func {name: "abs", arity: 1, f: |a|
+                int_result(a[0].checked_abs(), a[0].wrapping_abs(), a[0].saturating_abs())},
+            Func {name: "min", arity: 2, f: |a| Ok(a[0].min(a[1]))},
+            Func {name: "max", arity: 2, f: |a| Ok(a[0].max(a[1]))},
+            Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        ];
+    }
+    impl Funcs for u32 {
+        const FUNCS: &'static [Func<u32>] = &[
+            Func {name: "abs", arity: 1, f: |a| Ok(a[0])},
+            Func {name: "min", arity: 2, f: |a| Ok(a[0].min(a[1]))},
+            Func {name: "max", arity: 2, f: |a| Ok(a[0].max(a[1]))},
+            Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        ];
+    }
+
+    /// Result of a real function, NaN from a non-NaN argument means that it is outside the domain
+    fn real(func: &'static str, a: &[f64], v: f64) -> Result<f64, EvalError> {
+        if v.is_nan() && !a.iter().any(|x| x.is_nan()) {
+            Err(EvalError::InvalidArgument(func))
+        } else {
+            Ok(v)
+        }
+    }
+
+    impl Funcs for f64 {
+        const FUNCS: &'static [Func<f64>] = &[
+            Func {name: "abs", arity: 1, f: |a| Ok(a[0].abs())},
+            Func {name: "min", arity: 2, f: |a| Ok(a[0].min(a[1]))},
+            Func {name: "max", arity: 2, f: |a| Ok(a[0].max(a[1]))},
+            Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+            Func {name: "sqrt", arity: 1, f: |a| real("sqrt", a, a[0].sqrt())},
+            Func {name: "exp", arity: 1, f: |a| Ok(a[0].exp())},
+            Func {name: "ln", arity: 1, f: |a| real("ln", a, a[0].ln())},
+            Func {name: "log10", arity: 1, f: |a| real("log10", a, a[0].log10())},
+            Func {name: "sin", arity: 1, f: |a| real("sin", a, a[0].sin())},
+            Func {name: "cos", arity: 1, f: |a| real("cos", a, a[0].cos())},
+            Func {name: "tan", arity: 1, f: |a| real("tan", a, a[0].tan())},
+            Func {name: "asin", arity: 1, f: |a| real("asin", a, a[0].asin())},
+            Func {name: "acos", arity: 1, f: |a| real("acos", a, a[0].acos())},
+            Func {name: "atan", arity: 1, f: |a| Ok(a[0].atan())},
+            Func {name: "atan2", arity: 2, f: |a| Ok(a[0].atan2(a[1]))},
+            Func {name: "floor", arity: 1, f: |a| Ok(a[0].floor())},
+            Func {name: "ceil", arity: 1, f: |a| Ok(a[0].ceil())},
+            Func {name: "round", arity: 1, f: |a| Ok(a[0].round())},
+        ];
+    }
+    impl Funcs for String {
+        const FUNCS: &'static [Func<String>] = &[
+            Func {name: "len", arity: 1, f: |a| Ok(a[0].chars().count().to_string())},
+            Func {name: "upper", arity: 1, f: |a| Ok(a[0].to_uppercase())},
+            Func {name: "lower", arity: 1, f: |a| Ok(a[0].to_lowercase())},
+            Func {name: "trim", arity: 1, f: |a| Ok(a[0].trim().to_string())},
+        ];
     }
 }
 
 mod expr {
     use crate::TBound;
+    use crate::lexer::TerminalEnd;
     use crate::ops::*;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+    use std::collections::HashMap;
     use std::fmt::{Display, Error, Formatter};
+    use std::str::FromStr;
 
+    #[derive(Clone, Copy, PartialEq)]
     pub enum Op1Kind {
         Minus,
+        Not,
+        /// Bitwise complement `~x`
+        BitNot,
+        /// Factorial `x!`
+        Fact,
     }
     impl Op1Kind {
-        pub fn eval<T: Ops<T>>(&self, c: T) -> Option<T> {
+        /// All operators, in the order of declaration, which sizes the tables by operator
+        pub const ALL: [Op1Kind; 4] = [Op1Kind::Minus, Op1Kind::Not, Op1Kind::BitNot, Op1Kind::Fact];
+
+        pub fn eval<T: Ops<T>>(&self, c: Value<T>) -> Result<Value<T>, EvalError> {
+            let op = self.symbol();
             match *self {
-                Op1Kind::Minus => Ops::<T>::neg(&c),
+                Op1Kind::Minus => Ok(Value::Val(Ops::<T>::neg(&c.val(op)?)?)),
+                Op1Kind::Not => Ok(Value::Bool(!c.bool(op)?)),
+                Op1Kind::BitNot => Ok(Value::Val(Ops::<T>::bitnot(&c.val(op)?)?)),
+                Op1Kind::Fact => Ok(Value::Val(Ops::<T>::fact(&c.val(op)?)?)),
             }
         }
+        /// The entry of the operator in `OPERATORS`
+        pub fn def(&self) -> &'static OpDef {
+            &OPERATORS[OP1_DEFS[*self as usize]]
+        }
+        pub fn symbol(&self) -> &'static str {
+            self.def().symbol
+        }
     }
     impl Display for Op1Kind {
         fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
-            match self {
-                Op1Kind::Minus => write!(f, "-"),
-            }
+            write!(f, "{}", self.symbol())
         }
     }
+    #[derive(Clone, Copy, PartialEq)]
     pub enum Op2Kind {
         Add,
         Sub,
         Mul,
         Div,
+        Rem,
+        Pow,
+        BitAnd,
+        BitOr,
+        /// Bitwise exclusive or `x ^ y`, in types with bitwise operators
+        BitXor,
+        Shl,
+        Shr,
+        Eq,
+        Ne,
+        Lt,
+        Le,
+        Gt,
+        Ge,
+        And,
+        Or,
+        /// Conversion `x in UNIT`
+        In,
     }
     impl Op2Kind {
-        pub fn eval<T: Default + PartialEq + Ops<T>>(&self, l: T, r: T) -> Option<T> {
-            match *self {
-                Op2Kind::Add => Ops::<T>::add(&l, &r),
-                Op2Kind::Sub => Ops::<T>::sub(&l, &r),
-                Op2Kind::Mul => Ops::<T>::mul(&l, &r),
-                Op2Kind::Div =>
-                    if r != T::default() {
-                        Ops::<T>::div(&l, &r)
-                    } else {
-                        None
-                    },
-            }
+        /// All operators, in the order of declaration, which sizes the tables by operator
+        pub const ALL: [Op2Kind; 20] = [
+            Op2Kind::Add,
+            Op2Kind::Sub,
+            Op2Kind::Mul,
+            Op2Kind::Div,
+            Op2Kind::Rem,
+            Op2Kind::Pow,
+            Op2Kind::BitAnd,
+            Op2Kind::BitOr,
+            Op2Kind::BitXor,
+            Op2Kind::Shl,
+            Op2Kind::Shr,
+            Op2Kind::Eq,
+            Op2Kind::Ne,
+            Op2Kind::Lt,
+            Op2Kind::Le,
+            Op2Kind::Gt,
+            Op2Kind::Ge,
+            Op2Kind::And,
+            Op2Kind::Or,
+            Op2Kind::In,
+        ];
+
+        /// Evaluates the operator on evaluated operands, so `And` and `Or` do not short-circuit here
+        pub fn eval<T: Ops<T> + PartialEq>(&self, l: Value<T>, r: Value<T>) -> Result<Value<T>, EvalError> {
+            let op = self.symbol::<T>();
+            let cmp = |l: Value<T>, r: Value<T>| l.val(op)?.compare(&r.val(op)?);
+            let b = match *self {
+                Op2Kind::Add => return Ok(Value::Val(Ops::<T>::add(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Sub => return Ok(Value::Val(Ops::<T>::sub(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Mul => return Ok(Value::Val(Ops::<T>::mul(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Div => return Ok(Value::Val(Ops::<T>::div(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Rem => return Ok(Value::Val(Ops::<T>::rem(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Pow => return Ok(Value::Val(Ops::<T>::pow(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::BitAnd => return Ok(Value::Val(Ops::<T>::bitand(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::BitOr => return Ok(Value::Val(Ops::<T>::bitor(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::BitXor => return Ok(Value::Val(Ops::<T>::bitxor(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Shl => return Ok(Value::Val(Ops::<T>::shl(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Shr => return Ok(Value::Val(Ops::<T>::shr(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::In => return Ok(Value::Val(Ops::<T>::convert(&l.val(op)?, &r.val(op)?)?)),
+                Op2Kind::Eq | Op2Kind::Ne => {
+                    let eq = match (l, r) {
+                        (Value::Val(l), Value::Val(r)) => l.equal(&r)?,
+                        (Value::Bool(l), Value::Bool(r)) => Some(l == r),
+                        _ => return Err(EvalError::TypeMismatch(op)),
+                    };
+                    eq == Some(matches!(self, Op2Kind::Eq))
+                }
+                Op2Kind::Lt => cmp(l, r)? == Some(Ordering::Less),
+                Op2Kind::Le => matches!(cmp(l, r)?, Some(Ordering::Less | Ordering::Equal)),
+                Op2Kind::Gt => cmp(l, r)? == Some(Ordering::Greater),
+                Op2Kind::Ge => matches!(cmp(l, r)?, Some(Ordering::Greater | Ordering::Equal)),
+                Op2Kind::And => l.bool(op)? && r.bool(op)?,
+                Op2Kind::Or => l.bool(op)? || r.bool(op)?,
+            };
+            Ok(Value::Bool(b))
+        }
+        /// The entry of the operator in `OPERATORS` for type `T`
+        pub fn def<T: Ops<T>>(&self) -> &'static OpDef {
+            &OPERATORS[OP2_DEFS[T::bitwise() as usize][*self as usize]]
+        }
+        pub fn symbol<T: Ops<T>>(&self) -> &'static str {
+            self.def::<T>().symbol
+        }
+        /// Precedence level, a higher level binds tighter
+        pub fn prec<T: Ops<T>>(&self) -> u8 {
+            self.def::<T>().prec
         }
     }
-    impl Display for Op2Kind {
-        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Assoc {
+        Left,
+        Right,
+    }
+    /// An operator and the position of its symbol relative to the operands
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Notation {
+        Prefix(Op1Kind),
+        Postfix(Op1Kind),
+        Infix(Op2Kind, Assoc),
+    }
+    /// Types in which an entry of `OPERATORS` applies
+    #[derive(Clone, Copy, PartialEq)]
+    pub enum Types {
+        All,
+        /// Types with bitwise operators, see `Ops::bitwise`
+        Bitwise,
+        /// Types without bitwise operators
+        Other,
+    }
+    impl Types {
+        const fn include(self, bitwise: bool) -> bool {
             match self {
-                Op2Kind::Add => write!(f, "+"),
-                Op2Kind::Sub => write!(f, "-"),
-                Op2Kind::Mul => write!(f, "*"),
-                Op2Kind::Div => write!(f, "/"),
+                Types::All => true,
+                Types::Bitwise => bitwise,
+                Types::Other => !bitwise,
             }
         }
     }
+    /// Syntax of an operator
+    pub struct OpDef {
+        /// A symbol like `+`, or a word like `in`
+        pub symbol: &'static str,
+        /// A word like `add`, which names the operator in the JSON representation
+        pub name: &'static str,
+        /// Precedence level, a higher level binds tighter
+        pub prec: u8,
+        pub notation: Notation,
+        pub types: Types,
+    }
+    impl OpDef {
+        /// Whether the entry applies in type `T`
+        pub fn applies<T: Ops<T>>(&self) -> bool {
+            self.types.include(T::bitwise())
+        }
+    }
+
+    /// Indices of the first entries of unary operators in `OPERATORS`, by operator, which are
+    /// found at compile time as evaluation looks up symbols for errors
+    const OP1_DEFS: [usize; Op1Kind::ALL.len()] = {
+        let mut defs = [usize::MAX; Op1Kind::ALL.len()];
+        let mut i = OPERATORS.len();
+        while i > 0 {
+            i -= 1;
+            if let Notation::Prefix(op) | Notation::Postfix(op) = OPERATORS[i].notation {
+                defs[op as usize] = i;
+            }
+        }
+        let mut op = 0;
+        while op < defs.len() {
+            assert!(Op1Kind::ALL[op] as usize == op, "Op1Kind::ALL out of order");
+            assert!(defs[op] != usize::MAX, "operator without an entry in OPERATORS");
+            op += 1;
+        }
+        defs
+    };
+    /// Indices of the first entries of binary operators in `OPERATORS` for types without and
+    /// with bitwise operators, by operator. An operator without an entry in a type, which it
+    /// cannot be parsed in, falls back to its first entry.
+    const OP2_DEFS: [[usize; Op2Kind::ALL.len()]; 2] = [op2_defs(false), op2_defs(true)];
+    const fn op2_defs(bitwise: bool) -> [usize; Op2Kind::ALL.len()] {
+        let mut defs = [usize::MAX; Op2Kind::ALL.len()];
+        let mut fallback = defs;
+        let mut i = OPERATORS.len();
+        while i > 0 {
+            i -= 1;
+            if let Notation::Infix(op, _) = OPERATORS[i].notation {
+                fallback[op as usize] = i;
+                if OPERATORS[i].types.include(bitwise) {
+                    defs[op as usize] = i;
+                }
+            }
+        }
+        let mut op = 0;
+        while op < defs.len() {
+            assert!(Op2Kind::ALL[op] as usize == op, "Op2Kind::ALL out of order");
+            assert!(fallback[op] != usize::MAX, "operator without an entry in OPERATORS");
+            if defs[op] == usize::MAX {
+                defs[op] = fallback[op];
+            }
+            op += 1;
+        }
+        defs
+    }
+
+    pub const PREC_CONV: u8 = 0;
+    pub const PREC_COND: u8 = 1;
+    const PREC_UNARY: u8 = 12;
+    const PREC_POSTFIX: u8 = 14;
+    const PREC_PRIMARY: u8 = 15;
+
+    /// Operators of expressions, from which the parser and the display of expressions take
+    /// symbols, precedence and associativity. An operator with several symbols is displayed
+    /// by its first entry that applies in the type. Bitwise operators have the levels of C,
+    /// below comparisons, so `x & 1 == 0` is `x & (1 == 0)`. Power binds tighter than a prefix
+    /// operator on its left, so `-2^2` is `-(2^2)`, and a postfix operator binds tightest.
+    /// The conditional `c ? t : f` at level `PREC_COND` is parsed separately.
+    pub const OPERATORS: &[OpDef] = &[
+        OpDef {symbol: "in", name: "in", prec: PREC_CONV, notation: Notation::Infix(Op2Kind::In, Assoc::Left), types: Types::All},
+        OpDef {symbol: "||", name: "or", prec: 2, notation: Notation::Infix(Op2Kind::Or, Assoc::Left), types: Types::All},
+        OpDef {symbol: "&&", name: "and", prec: 3, notation: Notation::Infix(Op2Kind::And, Assoc::Left), types: Types::All},
+        OpDef {symbol: "|", name: "bitor", prec: 4, notation: Notation::Infix(Op2Kind::BitOr, Assoc::Left), types: Types::All},
+        OpDef {symbol: "^", name: "bitxor", prec: 5, notation: Notation::Infix(Op2Kind::BitXor, Assoc::Left), types: Types::Bitwise},
+        OpDef {symbol: "&", name: "bitand", prec: 6, notation: Notation::Infix(Op2Kind::BitAnd, Assoc::Left), types: Types::All},
+        OpDef {symbol: "==", name: "eq", prec: 7, notation: Notation::Infix(Op2Kind::Eq, Assoc::Left), types: Types::All},
+        OpDef {symbol: "!=", name: "ne", prec: 7, notation: Notation::Infix(Op2Kind::Ne, Assoc::Left), types: Types::All},
+        OpDef {symbol: "<", name: "lt", prec: 8, notation: Notation::Infix(Op2Kind::Lt, Assoc::Left), types: Types::All},
+        OpDef {symbol: "<=", name: "le", prec: 8, notation: Notation::Infix(Op2Kind::Le, Assoc::Left), types: Types::All},
+        OpDef {symbol: ">", name: "gt", prec: 8, notation: Notation::Infix(Op2Kind::Gt, Assoc::Left), types: Types::All},
+        OpDef {symbol: ">=", name: "ge", prec: 8, notation: Notation::Infix(Op2Kind::Ge, Assoc::Left), types: Types::All},
+        OpDef {symbol: "<<", name: "shl", prec: 9, notation: Notation::Infix(Op2Kind::Shl, Assoc::Left), types: Types::All},
+        OpDef {symbol: ">>", name: "shr", prec: 9, notation: Notation::Infix(Op2Kind::Shr, Assoc::Left), types: Types::All},
+        OpDef {symbol: "+", name: "add", prec: 10, notation: Notation::Infix(Op2Kind::Add, Assoc::Left), types: Types::All},
+        OpDef {symbol: "-", name: "sub", prec: 10, notation: Notation::Infix(Op2Kind::Sub, Assoc::Left), types: Types::All},
+        OpDef {symbol: "*", name: "mul", prec: 11, notation: Notation::Infix(Op2Kind::Mul, Assoc::Left), types: Types::All},
+        OpDef {symbol: "/", name: "div", prec: 11, notation: Notation::Infix(Op2Kind::Div, Assoc::Left), types: Types::All},
+        OpDef {symbol: "%", name: "rem", prec: 11, notation: Notation::Infix(Op2Kind::Rem, Assoc::Left), types: Types::All},
+        OpDef {symbol: "-", name: "neg", prec: PREC_UNARY, notation: Notation::Prefix(Op1Kind::Minus), types: Types::All},
+        OpDef {symbol: "!", name: "not", prec: PREC_UNARY, notation: Notation::Prefix(Op1Kind::Not), types: Types::All},
+        OpDef {symbol: "~", name: "bitnot", prec: PREC_UNARY, notation: Notation::Prefix(Op1Kind::BitNot), types: Types::All},
+        OpDef {symbol: "^", name: "pow", prec: 13, notation: Notation::Infix(Op2Kind::Pow, Assoc::Right), types: Types::Other},
+        OpDef {symbol: "**", name: "pow", prec: 13, notation: Notation::Infix(Op2Kind::Pow, Assoc::Right), types: Types::All},
+        OpDef {symbol: "!", name: "fact", prec: PREC_POSTFIX, notation: Notation::Postfix(Op1Kind::Fact), types: Types::All},
+    ];
+    #[derive(Clone, PartialEq)]
     pub enum Expr<T> {
         Value {
             v: T,
         },
+        Var {
+            name: String,
+        },
         Op1 {
             op: Op1Kind,
             child: Box<Expr<T>>
@@ -212,32 +1284,455 @@ mod expr {
             l: Box<Expr<T>>,
             r: Box<Expr<T>>,
         },
+        Call {
+            name: String,
+            args: Vec<Expr<T>>,
+        },
+        /// Conditional expression `c ? t : f`
+        Cond {
+            c: Box<Expr<T>>,
+            t: Box<Expr<T>>,
+            f: Box<Expr<T>>,
+        },
+    }
+
+    /// An evaluation error together with the innermost subexpression that failed
+    #[derive(Debug)]
+    pub struct ExprError {
+        pub error: EvalError,
+        pub at: String,
     }
+    impl Display for ExprError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            write!(f, "{} in {}", self.error, self.at)
+        }
+    }
+
+    /// A function defined by `fn NAME(PARAMS) = BODY`
+    pub struct UserFunc<T> {
+        pub params: Vec<String>,
+        pub body: Expr<T>,
+    }
+
+    /// Maximum limit of nested calls of user functions
+    pub const MAX_DEPTH_LIMIT: usize = 10_000;
+
+    thread_local! {
+        static MAX_DEPTH: Cell<usize> = const { Cell::new(1000) };
+    }
+    /// Sets the limit of nested calls of user functions, at most `MAX_DEPTH_LIMIT`
+    pub fn set_max_depth(n: usize) {
+        MAX_DEPTH.with(|c| c.set(n.min(MAX_DEPTH_LIMIT)));
+    }
+    fn max_depth() -> usize {
+        MAX_DEPTH.with(|c| c.get())
+    }
+    /// Maximum number of nested subexpressions and calls of user functions in evaluation, which
+    /// bounds the stack used by evaluation independently of the size of function bodies
+    pub const MAX_NESTING: usize = 50_000;
+    /// Values of variables and user functions used during evaluation
+    #[derive(Default)]
+    pub struct Env<T> {
+        vars: HashMap<String, Value<T>>,
+        funcs: HashMap<String, UserFunc<T>>,
+    }
+    impl<T> Env<T> {
+        pub fn get(&self, name: &str) -> Option<&Value<T>> {
+            self.vars.get(name)
+        }
+        pub fn set(&mut self, name: &str, v: Value<T>) {
+            self.vars.insert(name.to_string(), v);
+        }
+        /// Variables and their values in arbitrary order
+        pub fn vars(&self) -> impl Iterator<Item = (&String, &Value<T>)> {
+            self.vars.iter()
+        }
+        /// Defines user function `name`, replacing an earlier definition
+        pub fn define(&mut self, name: &str, f: UserFunc<T>) {
+            self.funcs.insert(name.to_string(), f);
+        }
+        /// User functions in arbitrary order
+        pub fn funcs(&self) -> impl Iterator<Item = (&String, &UserFunc<T>)> {
+            self.funcs.iter()
+        }
+    }
+    impl<T: TBound<T>> Env<T> {
+        /// Calls user function `name` with `depth` calls of user functions in progress, from
+        /// evaluation nested `level` deep. An error of the call itself, not of the evaluation of
+        /// the body, is located at `at`.
+        pub fn call(&self, name: &str, args: &[Value<T>], depth: usize, level: usize, at: impl FnOnce() -> String)
+            -> Result<Value<T>, ExprError>
+        {
+            let error = |error| Err(ExprError {error, at: at()});
+            let Some(f) = self.funcs.get(name) else {
+                return error(EvalError::UnknownFunction(name.to_string()));
+            };
+            if args.len() != f.params.len() {
+                return error(EvalError::ArgCount {func: name.to_string(), expected: f.params.len(), found: args.len()});
+            }
+            if depth >= max_depth() {
+                return error(EvalError::RecursionLimit(max_depth()));
+            }
+            f.body.eval_in(&Scope {env: self, params: &f.params, args, depth: depth + 1}, level + 1)
+        }
+    }
+
+    /// Variables visible in an expression. The body of a user function sees only its own
+    /// parameters and global variables, not variables of its caller.
+    struct Scope<'a, T> {
+        env: &'a Env<T>,
+        params: &'a [String],
+        args: &'a [Value<T>],
+        /// Number of calls of user functions in progress
+        depth: usize,
+    }
+    impl<'a, T> Scope<'a, T> {
+        fn get(&self, name: &str) -> Option<&'a Value<T>> {
+            match self.params.iter().position(|p| p == name) {
+                Some(i) => Some(&self.args[i]),
+                None => self.env.get(name),
+            }
+        }
+    }
+
     impl<T: TBound<T>> Expr<T> {
-        pub fn eval(&self) -> Option<T> {
+        pub fn eval_with(&self, env: &Env<T>) -> Result<Value<T>, ExprError> {
+            self.eval_in(&Scope {env, params: &[], args: &[], depth: 0}, 0)
+        }
+
+        /// Evaluates the expression nested `level` deep in evaluation
+        fn eval_in(&self, scope: &Scope<T>, level: usize) -> Result<Value<T>, ExprError> {
+            if level >= MAX_NESTING {
+                return Err(ExprError {error: EvalError::NestingLimit(MAX_NESTING), at: self.to_string()});
+            }
+            let level = level + 1;
+            let v = match self {
+                Expr::Value{v} => return Ok(Value::Val((*v).clone())),
+                Expr::Var{name} => scope.get(name).cloned().ok_or_else(|| EvalError::UnknownVariable(name.clone())),
+                Expr::Op1{op, child} => op.eval(child.eval_in(scope, level)?),
+                Expr::Op2{op: op @ (Op2Kind::And | Op2Kind::Or), l, r} => {
+                    match l.eval_in(scope, level)?.bool(op.symbol::<T>()) {
+                        Ok(b) if b == matches!(op, Op2Kind::Or) => Ok(Value::Bool(b)),
+                        Ok(_) => r.eval_in(scope, level)?.bool(op.symbol::<T>()).map(Value::Bool),
+                        Err(e) => Err(e),
+                    }
+                }
+                Expr::Op2{op, l, r} => op.eval(l.eval_in(scope, level)?, r.eval_in(scope, level)?),
+                // Names of built-in functions cannot be used by user functions
+                Expr::Call{name, args} if find_func::<T>(name).is_none() => {
+                    let mut vals = Vec::with_capacity(args.len());
+                    for a in args {
+                        vals.push(a.eval_in(scope, level)?);
+                    }
+                    return scope.env.call(name, &vals, scope.depth, level, || self.to_string());
+                }
+                Expr::Call{name, args} => {
+                    let mut vals = Vec::with_capacity(args.len());
+                    for a in args {
+                        match a.eval_in(scope, level)?.val("call") {
+                            Ok(v) => vals.push(v),
+                            Err(e) => return Err(ExprError {error: e, at: a.to_string()}),
+                        }
+                    }
+                    call(name, &vals).map(Value::Val)
+                }
+                Expr::Cond{c, t, f} => match c.eval_in(scope, level)?.bool("?:") {
+                    Ok(true) => return t.eval_in(scope, level),
+                    Ok(false) => return f.eval_in(scope, level),
+                    Err(e) => Err(e),
+                },
+            };
+            v.map_err(|error| ExprError {error, at: self.to_string()})
+        }
+
+        fn is_value(&self) -> bool {
+            matches!(self, Expr::Value{..})
+        }
+
+        fn is(&self, c: &Option<T>) -> bool {
+            matches!((self, c), (Expr::Value{v}, Some(c)) if v == c)
+        }
+
+        /// Whether a value of the expression is a value of `T`, not a boolean, assuming that
+        /// variables and results of user functions are values of `T`
+        fn is_numeric(&self) -> bool {
+            match self {
+                Expr::Value{..} | Expr::Var{..} | Expr::Call{..} => true,
+                Expr::Op1{op, ..} => *op != Op1Kind::Not,
+                Expr::Op2{op, ..} => !matches!(op, Op2Kind::Eq | Op2Kind::Ne | Op2Kind::Lt | Op2Kind::Le
+                    | Op2Kind::Gt | Op2Kind::Ge | Op2Kind::And | Op2Kind::Or),
+                Expr::Cond{t, f, ..} => t.is_numeric() && f.is_numeric(),
+            }
+        }
+
+        /// Whether the simplified expression contains an operator or a call of a built-in
+        /// function with constant operands, which did not fold as its evaluation fails or has
+        /// a boolean value
+        fn has_unfolded(&self) -> bool {
             match self {
-                Expr::Value{v} => Some((*v).clone()),
-                Expr::Op1{op, child} =>
-                    if let Some(cv) = child.eval() {
-                        op.eval(cv)
+                Expr::Value{..} | Expr::Var{..} => false,
+                Expr::Op1{child, ..} => child.is_value() || child.has_unfolded(),
+                Expr::Op2{l, r, ..} => l.is_value() && r.is_value() || l.has_unfolded() || r.has_unfolded(),
+                Expr::Call{name, args} => {
+                    find_func::<T>(name).is_some() && args.iter().all(Expr::is_value) || args.iter().any(Expr::has_unfolded)
+                }
+                Expr::Cond{c, t, f} => c.has_unfolded() || t.has_unfolded() || f.has_unfolded(),
+            }
+        }
+
+        /// Whether an identity may remove the simplified expression, which has a value of `T`,
+        /// assuming that it evaluates without an error unless a constant subexpression did not fold
+        fn is_removable(&self) -> bool {
+            self.is_numeric() && !self.has_unfolded()
+        }
+
+        /// Folds constant subexpressions and applies algebraic identities that hold for type `T`
+        /// according to its `Ops<T>`. Identities only replace an operand by itself if it has a
+        /// value of `T`, and only remove operands by `is_removable`, so a subexpression like `x`
+        /// in `x * 0` is assumed to evaluate without an error, but not `1 / 0` in `1 / 0 * 0`.
+        pub fn simplify(self) -> Expr<T> {
+            let e = match self {
+                Expr::Value{..} | Expr::Var{..} => return self,
+                Expr::Op1{op, child} => Expr::Op1 {op, child: Box::new(child.simplify())},
+                Expr::Op2{op, l, r} => Expr::Op2 {op, l: Box::new(l.simplify()), r: Box::new(r.simplify())},
+                Expr::Call{name, args} => Expr::Call {name, args: args.into_iter().map(Expr::simplify).collect()},
+                Expr::Cond{c, t, f} => {
+                    let c = c.simplify();
+                    return match c.eval_with(&Env::default()) {
+                        Ok(Value::Bool(true)) => t.simplify(),
+                        Ok(Value::Bool(false)) => f.simplify(),
+                        _ => Expr::Cond {c: Box::new(c), t: Box::new(t.simplify()), f: Box::new(f.simplify())},
+                    };
+                }
+            };
+            let constant = match &e {
+                Expr::Op1{child, ..} => child.is_value(),
+                Expr::Op2{l, r, ..} => l.is_value() && r.is_value(),
+                Expr::Call{args, ..} => args.iter().all(Expr::is_value),
+                _ => false,
+            };
+            if constant {
+                if let Ok(Value::Val(v)) = e.eval_with(&Env::default()) {
+                    return Expr::Value {v};
+                }
+            }
+            let (zero, one) = (T::zero(), T::one());
+            match e {
+                Expr::Op1{op: Op1Kind::Minus, child} if T::neg_involutive() => match *child {
+                    Expr::Op1{op: Op1Kind::Minus, child} if child.is_numeric() => *child,
+                    child => Expr::Op1 {op: Op1Kind::Minus, child: Box::new(child)},
+                },
+                Expr::Op2{op, l, r} => match op {
+                    Op2Kind::Add if !T::signed_zero() && l.is(&zero) && r.is_numeric() => *r,
+                    Op2Kind::Add if !T::signed_zero() && r.is(&zero) && l.is_numeric() => *l,
+                    Op2Kind::Sub if r.is(&zero) && l.is_numeric() => *l,
+                    Op2Kind::Sub if T::annihilates() && zero.is_some() && l == r && l.is_removable() => {
+                        Expr::Value {v: zero.unwrap()}
+                    }
+                    Op2Kind::Mul if l.is(&one) && r.is_numeric() => *r,
+                    Op2Kind::Mul | Op2Kind::Div | Op2Kind::Pow if r.is(&one) && l.is_numeric() => *l,
+                    Op2Kind::Mul if T::annihilates() && (l.is(&zero) && r.is_removable() || r.is(&zero) && l.is_removable()) => {
+                        Expr::Value {v: zero.unwrap()}
+                    }
+                    Op2Kind::Pow if one.is_some() && r.is(&zero) && l.is_removable() => Expr::Value {v: one.unwrap()},
+                    _ => Expr::Op2 {op, l, r},
+                },
+                e => e,
+            }
+        }
+    }
+    /// Text of value `v` in an infix expression. A negative integer whose magnitude is out of
+    /// range, like `-2147483648` of i32, is written as `-2147483647 - 1`.
+    fn value_text<T: Display + FromStr + TerminalEnd>(v: &T) -> String {
+        let s = v.to_literal();
+        let Some(m) = s.strip_prefix('-').filter(|m| m.bytes().all(|b| b.is_ascii_digit())) else {
+            return s;
+        };
+        let mut d = m.as_bytes().to_vec();
+        let Some(i) = d.iter().rposition(|&b| b != b'0') else {
+            return s;
+        };
+        d[i] -= 1;
+        d[i + 1..].fill(b'9');
+        let d = String::from_utf8(d).unwrap();
+        match (T::parse_literal(m), T::parse_literal(&d)) {
+            (None, Some(_)) => format!("-{d} - 1"),
+            _ => s,
+        }
+    }
+
+    impl<T: Display + FromStr + TerminalEnd + Ops<T>> Expr<T> {
+        fn prec(&self) -> u8 {
+            match self {
+                Expr::Value{v} => {
+                    // A value may be displayed as several tokens, like `-1`, `1+2i` or `3/4`
+                    let s = value_text(v);
+                    if T::literal(&s) == s.len() {
+                        PREC_PRIMARY
+                    } else if s.chars().skip(1).any(|c| c == '+' || c == '-') {
+                        Op2Kind::Add.prec::<T>()
+                    } else if s.contains('/') {
+                        Op2Kind::Div.prec::<T>()
+                    } else if s.starts_with('-') {
+                        PREC_UNARY
                     } else {
-                        None
-                    },
-                Expr::Op2{op, l, r} =>
-                    if let (Some(lv), Some(rv)) = (l.eval(), r.eval()) {
-                        op.eval(lv, rv)
+                        PREC_PRIMARY
+                    }
+                }
+                Expr::Var{..} | Expr::Call{..} => PREC_PRIMARY,
+                Expr::Op1{op, ..} => op.def().prec,
+                Expr::Op2{op, ..} => op.prec::<T>(),
+                Expr::Cond{..} => PREC_COND,
+            }
+        }
+
+        /// Writes the expression with only the parentheses required by precedence and
+        /// associativity of operators, enclosing it in parentheses if it binds weaker than `min`
+        fn fmt_min(&self, f: &mut Formatter<'_>, min: u8) -> Result<(), Error> {
+            let paren = self.prec() < min;
+            if paren {
+                write!(f, "(")?;
+            }
+            match self {
+                Expr::Value{v} => write!(f, "{}", value_text(v))?,
+                Expr::Var{name} => write!(f, "{name}")?,
+                Expr::Op1{op, child} => {
+                    if let Notation::Postfix(_) = op.def().notation {
+                        child.fmt_min(f, PREC_POSTFIX)?;
+                        write!(f, "{op}")?;
                     } else {
-                        None
+                        write!(f, "{op}")?;
+                        child.fmt_min(f, PREC_UNARY)?;
                     }
+                }
+                Expr::Op2{op, l, r} => {
+                    // A prefix operator may begin a right operand at any level, like in `2 ^ -x`
+                    let (lp, rp) = match op.def::<T>().notation {
+                        Notation::Infix(_, Assoc::Right) => (op.prec::<T>() + 1, op.prec::<T>().min(PREC_UNARY)),
+                        _ => (op.prec::<T>(), op.prec::<T>() + 1),
+                    };
+                    l.fmt_min(f, lp)?;
+                    write!(f, " {} ", op.symbol::<T>())?;
+                    r.fmt_min(f, rp)?;
+                }
+                Expr::Call{name, args} => {
+                    write!(f, "{name}(")?;
+                    for (i, a) in args.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        a.fmt_min(f, PREC_COND)?;
+                    }
+                    write!(f, ")")?;
+                }
+                Expr::Cond{c, t, f: e} => {
+                    c.fmt_min(f, PREC_COND + 1)?;
+                    write!(f, " ? ")?;
+                    t.fmt_min(f, PREC_COND)?;
+                    write!(f, " : ")?;
+                    e.fmt_min(f, PREC_COND)?;
+                }
+            }
+            if paren {
+                write!(f, ")")?;
+            }
+            Ok(())
+        }
+
+        /// Writes the expression with each subexpression in parentheses
+        fn fmt_full(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            match self {
+                Expr::Value{v} => write!(f, "({})", value_text(v)),
+                Expr::Var{name} => write!(f, "({name})"),
+                Expr::Op1{op, child} => match op.def().notation {
+                    Notation::Postfix(_) => write!(f, "({child:#}{op})"),
+                    _ => write!(f, "({op}{child:#})"),
+                },
+                Expr::Op2{op, l, r} => write!(f, "({l:#}{}{r:#})", op.symbol::<T>()),
+                Expr::Call{name, args} => {
+                    write!(f, "({name}(")?;
+                    for (i, a) in args.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, "{a:#}")?;
+                    }
+                    write!(f, "))")
+                }
+                Expr::Cond{c, t, f: e} => write!(f, "({c:#}?{t:#}:{e:#})"),
             }
         }
     }
-    impl<T: Display> Display for Expr<T> {
+    /// Displays the expression with minimal parentheses, or fully parenthesized in the alternate
+    /// form `{:#}`. The minimal form of a parsed expression parses back to an equal expression.
+    impl<T: Display + FromStr + TerminalEnd + Ops<T>> Display for Expr<T> {
         fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            if f.alternate() {
+                self.fmt_full(f)
+            } else {
+                self.fmt_min(f, PREC_CONV)
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub enum Stmt<T> {
+        Let {
+            name: String,
+            e: Expr<T>,
+        },
+        Fn {
+            name: String,
+            params: Vec<String>,
+            body: Expr<T>,
+        },
+        Expr(Expr<T>),
+    }
+    impl<T: TBound<T>> Stmt<T> {
+        /// Evaluates the statement, a `let` also stores the value in `env`.
+        /// A `fn` defines a function in `env` and has no value.
+        pub fn exec(&self, env: &mut Env<T>) -> Result<Option<Value<T>>, ExprError> {
+            match self {
+                Stmt::Let{name, e} => {
+                    let v = e.eval_with(env)?;
+                    env.set(name, v.clone());
+                    Ok(Some(v))
+                }
+                Stmt::Fn{name, ..} if find_func::<T>(name).is_some() => {
+                    Err(ExprError {error: EvalError::Redefined(name.clone()), at: self.to_string()})
+                }
+                Stmt::Fn{name, params, body} => {
+                    env.define(name, UserFunc {params: params.clone(), body: body.clone()});
+                    Ok(None)
+                }
+                Stmt::Expr(e) => e.eval_with(env).map(Some),
+            }
+        }
+        pub fn simplify(self) -> Stmt<T> {
             match self {
-                Expr::Value{v} => write!(f, "({v})"),
-                Expr::Op1{op, child} => write!(f, "({op}{child})"),
-                Expr::Op2{op, l, r} => write!(f, "({l}{op}{r})"),
+                Stmt::Let{name, e} => Stmt::Let {name, e: e.simplify()},
+                Stmt::Fn{name, params, body} => Stmt::Fn {name, params, body: body.simplify()},
+                Stmt::Expr(e) => Stmt::Expr(e.simplify()),
+            }
+        }
+    }
+    impl<T: Display + FromStr + TerminalEnd + Ops<T>> Display for Stmt<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            let e = match self {
+                Stmt::Let{name, e} => {
+                    write!(f, "let {name} = ")?;
+                    e
+                }
+                Stmt::Fn{name, params, body} => {
+                    write!(f, "fn {name}({}) = ", params.join(", "))?;
+                    body
+                }
+                Stmt::Expr(e) => e,
+            };
+            if f.alternate() {
+                write!(f, "{e:#}")
+            } else {
+                write!(f, "{e}")
             }
         }
     }
@@ -245,145 +1740,743 @@ mod expr {
 
 mod parser {
     use crate::TBound;
-    use super::expr::{Op1Kind, Op2Kind, Expr};
+    use crate::lexer::{tokenize, Token, TokenKind};
+    use super::expr::{Assoc, Expr, Notation, OpDef, Stmt, MAX_NESTING, OPERATORS, PREC_COND, PREC_CONV};
+    use std::fmt::{Display, Error, Formatter};
 
-    pub fn parse<T: TBound<T>>(s: &str) -> Option<Expr<T>> {
-        expression::<T>(s.trim()).0
+    /// A syntax error, located by its byte offset `pos` and character offset `col` in the input
+    #[derive(Debug)]
+    pub struct ParseError {
+        pub pos: usize,
+        pub col: usize,
+        pub expected: &'static str,
+        pub found: String,
+    }
+    impl ParseError {
+        /// Writes the input line and a caret under the position of the error
+        pub fn caret(&self, input: &str) -> String {
+            let line = input.trim_end();
+            let indent: String = line.chars().take(self.col)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            format!("{line}\n{indent}^")
+        }
+    }
+    impl Display for ParseError {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+            write!(f, "expected {}, found {} at character {} (byte {})",
+                self.expected, self.found, self.col, self.pos)
+        }
     }
 
-    fn expression<T: TBound<T>>(s: &str) -> (Option<Expr<T>>, &str) {
-        if let (Some(mut t1), mut s) = term::<T>(s) {
-            loop {
-                s = s.trim_start();
-                if s.is_empty() {
-                    return (Some(t1), &s);
-                }
-                let op: Op2Kind;
-                match s.chars().next().unwrap() {
-                    '+' => op = Op2Kind::Add,
-                    '-' => op = Op2Kind::Sub,
-                    _ => return (Some(t1), &s)
-                }
-                s = &s[1..];
-                if let(Some(t2), s2) = term::<T>(s) {
-                    s = &s2;
-                    t1 = Expr::Op2 {op, l: Box::new(t1), r: Box::new(t2)};
-                } else {
-                    return (None, s)
+    pub fn parse<T: TBound<T>>(s: &str) -> Result<Expr<T>, ParseError> {
+        let mut p = Parser::<T>::new(s)?;
+        let e = p.expression(PREC_CONV)?;
+        if !p.at_end() {
+            return Err(p.error("operator or end of input"));
+        }
+        Ok(e)
+    }
+
+    /// Parses a sequence of statements separated by `;`
+    pub fn parse_script<T: TBound<T>>(s: &str) -> Result<Vec<Stmt<T>>, ParseError> {
+        let mut p = Parser::<T>::new(s)?;
+        let mut stmts = Vec::new();
+        loop {
+            stmts.push(p.statement()?);
+            if p.symbol(";") {
+                if p.at_end() {
+                    return Ok(stmts);
                 }
+            } else if p.at_end() {
+                return Ok(stmts);
+            } else {
+                return Err(p.error("operator, ';' or end of input"));
             }
-        } else {
-            (None, &s)
         }
     }
 
-    fn term<T: TBound<T>>(s: &str) -> (Option<Expr<T>>, &str) {
-        if let (Some(mut f1), mut s) = factor::<T>(s) {
-            loop {
-                s = s.trim_start();
-                if s.is_empty() {
-                    return (Some(f1), &s);
+    struct Parser<'a, T> {
+        tokens: Vec<Token<'a, T>>,
+        /// Index of the next token
+        i: usize,
+        /// Number of subexpressions being parsed, which contain the next token
+        depth: usize,
+    }
+
+    /// An expression parsed and its height, the number of nodes on its longest path from the root
+    type Parsed<T> = (Expr<T>, usize);
+
+    impl<'a, T: TBound<T>> Parser<'a, T> {
+        fn new(input: &'a str) -> Result<Parser<'a, T>, ParseError> {
+            Ok(Parser {tokens: tokenize(input)?, i: 0, depth: 0})
+        }
+
+        fn peek(&self) -> &TokenKind<'a, T> {
+            &self.tokens[self.i].kind
+        }
+
+        fn at_end(&self) -> bool {
+            matches!(self.peek(), TokenKind::End)
+        }
+
+        fn bump(&mut self) {
+            if !self.at_end() {
+                self.i += 1;
+            }
+        }
+
+        fn error(&self, expected: &'static str) -> ParseError {
+            let t = &self.tokens[self.i];
+            let found = match t.kind {
+                TokenKind::End => String::from("end of input"),
+                _ => format!("'{}'", t.text),
+            };
+            ParseError { pos: t.pos, col: t.col, expected, found }
+        }
+
+        /// Consumes keyword `kw` if it is the next token
+        fn keyword(&mut self, kw: &str) -> bool {
+            let found = matches!(self.peek(), TokenKind::Ident(name) if *name == kw);
+            if found {
+                self.bump();
+            }
+            found
+        }
+
+        fn identifier(&mut self) -> Option<&'a str> {
+            let TokenKind::Ident(name) = *self.peek() else {
+                return None;
+            };
+            self.bump();
+            Some(name)
+        }
+
+        /// Consumes the name of a new variable, function or parameter
+        fn new_name(&mut self, expected: &'static str) -> Result<String, ParseError> {
+            // Results `$1` and `$_` are set only by the REPL
+            if matches!(self.peek(), TokenKind::Ident(name) if name.starts_with('$')) {
+                return Err(self.error(expected));
+            }
+            self.identifier().map(str::to_string).ok_or_else(|| self.error(expected))
+        }
+
+        fn statement(&mut self) -> Result<Stmt<T>, ParseError> {
+            if self.keyword("fn") {
+                return self.definition();
+            }
+            if !self.keyword("let") {
+                return Ok(Stmt::Expr(self.expression(PREC_CONV)?));
+            }
+            let name = self.new_name("variable name")?;
+            if !self.symbol("=") {
+                return Err(self.error("'='"));
+            }
+            Ok(Stmt::Let {name, e: self.expression(PREC_CONV)?})
+        }
+
+        /// Parses a function definition `NAME(PARAMS) = BODY` after keyword `fn`
+        fn definition(&mut self) -> Result<Stmt<T>, ParseError> {
+            let name = self.new_name("function name")?;
+            if !self.symbol("(") {
+                return Err(self.error("'('"));
+            }
+            let mut params: Vec<String> = Vec::new();
+            if !self.symbol(")") {
+                loop {
+                    if matches!(self.peek(), TokenKind::Ident(p) if params.iter().any(|q| q == p)) {
+                        return Err(self.error("distinct parameter name"));
+                    }
+                    params.push(self.new_name("parameter name")?);
+                    if self.symbol(")") {
+                        break;
+                    }
+                    if !self.symbol(",") {
+                        return Err(self.error("',' or ')'"));
+                    }
                 }
-                let op: Op2Kind;
-                match s.chars().next().unwrap() {
-                    '*' => op = Op2Kind::Mul,
-                    '/' => op = Op2Kind::Div,
-                    _ => return (Some(f1), &s)
+            }
+            if !self.symbol("=") {
+                return Err(self.error("'='"));
+            }
+            Ok(Stmt::Fn {name, params, body: self.expression(PREC_CONV)?})
+        }
+
+        /// Consumes operator or punctuation `sym` if it is the next token
+        fn symbol(&mut self, sym: &str) -> bool {
+            let found = matches!(self.peek(), TokenKind::Symbol(s) if *s == sym);
+            if found {
+                self.bump();
+            }
+            found
+        }
+
+        /// The entry of `OPERATORS` for the next token, a prefix operator if `prefix`,
+        /// otherwise an infix or postfix operator
+        fn operator(&self, prefix: bool) -> Option<&'static OpDef> {
+            let symbol = match *self.peek() {
+                TokenKind::Symbol(s) => s,
+                TokenKind::Ident(s) => s,
+                _ => return None,
+            };
+            OPERATORS.iter().find(|d| {
+                d.symbol == symbol && matches!(d.notation, Notation::Prefix(_)) == prefix && d.applies::<T>()
+            })
+        }
+
+        fn expression(&mut self, min: u8) -> Result<Expr<T>, ParseError> {
+            self.nested(min).map(|(e, _)| e)
+        }
+
+        /// Parses an expression of operators at level `min` or higher by precedence climbing.
+        /// Subexpressions and trees nested deeper than `MAX_NESTING`, which could not be
+        /// evaluated, are rejected before they overflow the stack of parsing.
+        fn nested(&mut self, min: u8) -> Result<Parsed<T>, ParseError> {
+            if self.depth == MAX_NESTING {
+                return Err(self.error("less deeply nested expression"));
+            }
+            self.depth += 1;
+            let (mut l, mut height) = match self.operator(true) {
+                Some(&OpDef {prec, notation: Notation::Prefix(op), ..}) => {
+                    self.bump();
+                    let (child, h) = self.nested(prec)?;
+                    self.node(Expr::Op1 {op, child: Box::new(child)}, h)?
                 }
-                s = &s[1..];
-                if let(Some(f2), s2) = factor::<T>(s) {
-                    s = &s2;
-                    f1 = Expr::Op2 {op, l: Box::new(f1), r: Box::new(f2)};
-                } else {
-                    return (None, s);
+                _ => self.primary()?,
+            };
+            loop {
+                if min <= PREC_COND && self.symbol("?") {
+                    (l, height) = self.conditional(l, height)?;
+                    continue;
                 }
+                let def = match self.operator(false) {
+                    Some(def) if def.prec >= min => def,
+                    _ => break,
+                };
+                (l, height) = match def.notation {
+                    Notation::Postfix(op) => {
+                        self.bump();
+                        self.node(Expr::Op1 {op, child: Box::new(l)}, height)?
+                    }
+                    Notation::Infix(op, assoc) => {
+                        self.bump();
+                        let (r, h) = self.nested(if assoc == Assoc::Left { def.prec + 1 } else { def.prec })?;
+                        self.node(Expr::Op2 {op, l: Box::new(l), r: Box::new(r)}, height.max(h))?
+                    }
+                    Notation::Prefix(_) => break,
+                };
             }
-        } else {
-            (None, &s)
+            // After an error, the depth is not restored, as parsing stops
+            self.depth -= 1;
+            Ok((l, height))
         }
-    }
 
-    fn factor<T: TBound<T>>(s: &str) -> (Option<Expr<T>>, &str) {
-        let mut s = s.trim_start();
-        if s.is_empty() {
-            return (None, s);
+        /// Node `e` over subexpressions of height at most `height`
+        fn node(&self, e: Expr<T>, height: usize) -> Result<Parsed<T>, ParseError> {
+            if height == MAX_NESTING {
+                return Err(self.error("less deeply nested expression"));
+            }
+            Ok((e, height + 1))
         }
-        let mut m: Option<Op1Kind> = None;
-        match s.chars().next().unwrap() {
-            '-' => {
-                m = Some(Op1Kind::Minus);
-                s = &s[1..];
+
+        /// Parses the rest of a conditional `c ? t : f` after `?`
+        fn conditional(&mut self, c: Expr<T>, height: usize) -> Result<Parsed<T>, ParseError> {
+            let (t, ht) = self.nested(PREC_COND)?;
+            if !self.symbol(":") {
+                return Err(self.error("':'"));
             }
-            _ => {}
+            let (f, hf) = self.nested(PREC_COND)?;
+            self.node(Expr::Cond {c: Box::new(c), t: Box::new(t), f: Box::new(f)}, height.max(ht).max(hf))
         }
-        s = s.trim_start();
-        if s.is_empty() {
-            return (None, s);
+
+        fn primary(&mut self) -> Result<Parsed<T>, ParseError> {
+            if !self.symbol("(") {
+                return self.terminal();
+            }
+            let e = self.nested(PREC_CONV)?;
+            if !self.symbol(")") {
+                return Err(self.error("')'"));
+            }
+            Ok(e)
         }
-        let mut e: Option<Expr<T>>;
-        match s.chars().next().unwrap() {
-            '(' => {
-                s = &s[1..];
-                (e, s) = expression::<T>(s);
-                if e.is_some() {
-                    s = s.trim_start();
-                    if s.is_empty() {
-                        return (None, s);
+
+        /// Parses arguments of a function call after the opening parenthesis
+        fn call(&mut self, name: &str) -> Result<Parsed<T>, ParseError> {
+            let mut args = Vec::new();
+            let mut height = 0;
+            if !self.symbol(")") {
+                loop {
+                    let (a, h) = self.nested(PREC_CONV)?;
+                    args.push(a);
+                    height = height.max(h);
+                    if self.symbol(")") {
+                        break;
                     }
-                    match s.chars().next().unwrap() {
-                        ')' => s = &s[1..],
-                        _ => return (None, s),
+                    if !self.symbol(",") {
+                        return Err(self.error("',' or ')'"));
                     }
                 }
             }
-            _ => (e, s) = terminal::<T>(s),
+            self.node(Expr::Call {name: name.to_string(), args}, height)
         }
-        if let Some(op) = m {
-            e = Some(Expr::Op1 {op, child: Box::new(e.unwrap())});
+
+        fn terminal(&mut self) -> Result<Parsed<T>, ParseError> {
+            if let TokenKind::Value(v) = self.peek() {
+                let v = v.clone();
+                self.bump();
+                return Ok((Expr::Value {v}, 1));
+            }
+            let Some(name) = self.identifier() else {
+                return Err(self.error("value, variable, unary operator or '('"));
+            };
+            if self.symbol("(") {
+                self.call(name)
+            } else {
+                Ok((Expr::Var {name: name.to_string()}, 1))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variable_arguments() {
+        assert!(is_var_name("x"));
+        assert!(is_var_name("_rate2"));
+        assert!(!is_var_name(""));
+        assert!(!is_var_name("2x"));
+        assert!(!is_var_name("--scal"));
+        assert!(!is_var_name("a b"));
+        assert!(!is_var_name("x+y"));
+    }
+
+    /// Runs the statements of `script`, returning the display of the last value or the first error
+    pub fn eval<T: TBound<T>>(script: &str) -> Result<String, String> {
+        let mut env = Env::default();
+        let mut v = None;
+        for stmt in parser::parse_script::<T>(script).map_err(|e| e.to_string())? {
+            v = stmt.exec(&mut env).map_err(|e| e.error.to_string())?;
         }
-        (e, s)
+        Ok(v.map(|v| v.to_string()).unwrap_or_default())
+    }
+
+    /// The expression `s` with each subexpression in parentheses
+    pub fn parens<T: TBound<T>>(s: &str) -> String {
+        parser::parse::<T>(s).map(|e| format!("{e:#}")).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn short_circuit() {
+        assert_eq!(eval::<i32>("1 > 2 && 1 / 0 == 1"), Ok(String::from("false")));
+        assert_eq!(eval::<i32>("1 < 2 || 1 / 0 == 1"), Ok(String::from("true")));
+        assert_eq!(eval::<i32>("1 < 2 ? 3 : 1 / 0"), Ok(String::from("3")));
+        assert_eq!(eval::<i32>("1 > 2 ? 1 / 0 : 4"), Ok(String::from("4")));
+        assert_eq!(eval::<i32>("1 < 2 && 1 / 0 == 1"), Err(String::from("division by zero")));
+        assert_eq!(eval::<i32>("1 > 2 || 1 / 0 == 1"), Err(String::from("division by zero")));
+        assert_eq!(eval::<i32>("1 > 2 ? 3 : 1 / 0"), Err(String::from("division by zero")));
+        assert_eq!(eval::<i32>("1 && 1 < 2"), Err(String::from("operator && not supported for type i32")));
+    }
+
+    #[test]
+    fn complex_comparisons() {
+        assert_eq!(eval::<complex::Complex>("(1 + 2i) * i == -2 + i"), Ok(String::from("true")));
+        assert_eq!(eval::<complex::Complex>("2i != 2"), Ok(String::from("true")));
+        assert_eq!(eval::<complex::Complex>("i < 2i"), Err(String::from("operator comparison not supported for type complex")));
+    }
+
+    #[test]
+    fn power_binds_tighter_than_minus() {
+        assert_eq!(parens::<f64>("-2^2"), "(-((2)^(2)))");
+        assert_eq!(eval::<f64>("-2^2"), Ok(String::from("-4")));
+        assert_eq!(eval::<f64>("(-2)^2"), Ok(String::from("4")));
+    }
+
+    #[test]
+    fn power_is_right_associative() {
+        assert_eq!(parens::<f64>("2^3^2"), "((2)^((3)^(2)))");
+        assert_eq!(eval::<f64>("2^3^2"), Ok(String::from("512")));
+        assert_eq!(parens::<f64>("2**3**2"), "((2)^((3)^(2)))");
+        assert_eq!(eval::<f64>("2**3**2"), Ok(String::from("512")));
+    }
+
+    #[test]
+    fn power_of_negative_exponent() {
+        assert_eq!(parens::<f64>("2^-1"), "((2)^(-(1)))");
+        assert_eq!(eval::<f64>("2^-1"), Ok(String::from("0.5")));
+        assert_eq!(eval::<f64>("2^-1^2"), Ok(String::from("0.5")));
+    }
+
+    #[test]
+    fn remainder_precedence() {
+        assert_eq!(parens::<i32>("1 + 7 % 4 * 2"), "((1)+(((7)%(4))*(2)))");
+        assert_eq!(eval::<i32>("1 + 7 % 4 * 2"), Ok(String::from("7")));
+        assert_eq!(eval::<i32>("2 * 7 % 4"), Ok(String::from("2")));
+        assert_eq!(eval::<i32>("-7 % 3"), Ok(String::from("-1")));
+        assert_eq!(eval::<i32>("2**3 % 3"), Ok(String::from("2")));
+    }
+
+    /// The expression `s` simplified
+    fn simplified<T: TBound<T>>(s: &str) -> String {
+        parser::parse::<T>(s).map(|e| e.simplify().to_string()).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    #[test]
+    fn additive_identity() {
+        assert_eq!(simplified::<f64>("x + 0"), "x + 0");
+        assert_eq!(simplified::<f64>("0 + x"), "0 + x");
+        assert_eq!(simplified::<f64>("x - 0"), "x");
+        assert_eq!(simplified::<complex::Complex>("x + 0"), "x + 0");
+        assert_eq!(simplified::<interval::Interval>("0 + x"), "0 + x");
+        assert_eq!(simplified::<i32>("x + 0"), "x");
+        assert_eq!(simplified::<i32>("0 + x"), "x");
+        assert_eq!(eval::<f64>("let x = -0.0; x + 0"), Ok(String::from("0")));
+    }
+
+    #[test]
+    fn identities_keep_booleans_and_errors() {
+        assert_eq!(simplified::<i32>("(x < 2) * 1"), "(x < 2) * 1");
+        assert_eq!(simplified::<i32>("(1 / 0) * 0"), "1 / 0 * 0");
+        assert_eq!(simplified::<i32>("1 + 0 * (1 / 0)"), "1 + 0 * (1 / 0)");
+        assert_eq!(simplified::<i32>("(x < 2) - (x < 2)"), "(x < 2) - (x < 2)");
+        assert_eq!(simplified::<i32>("(x == 1) + 0"), "(x == 1) + 0");
+        assert_eq!(simplified::<i32>("--(x > 1)"), "--(x > 1)");
+        assert_eq!(simplified::<i32>("(1 < 2) ** 0"), "(1 < 2) ** 0");
+        assert_eq!(simplified::<i32>("(x < 0 ? 1 : x > 2) * 1"), "(x < 0 ? 1 : x > 2) * 1");
+        assert_eq!(simplified::<i32>("abs(x - 1) * 0 + 1"), "1");
+        assert_eq!(simplified::<i32>("(x + y) * 0 + (x % y - x % y) + (y * 1 + 0)"), "y");
+        assert_eq!(simplified::<i32>("(x < 0 ? 1 : 2) * 1 - 0"), "x < 0 ? 1 : 2");
+        assert_eq!(eval::<i32>("let x = 1; (x < 2) * 1"), Err(String::from("operator * not supported for type bool")));
+        assert_eq!(eval::<i32>("1 + 0 * (1 / 0)"), Err(String::from("division by zero")));
+    }
+
+    #[test]
+    fn exclusive_or() {
+        assert_eq!(eval::<i32>("2 ^ 3"), Ok(String::from("1")));
+        assert_eq!(eval::<u32>("6 ^ 3"), Ok(String::from("5")));
+        assert_eq!(eval::<i32>("2 ** 3"), Ok(String::from("8")));
+        assert_eq!(eval::<f64>("2 ^ 3"), Ok(String::from("8")));
+        assert_eq!(parens::<i32>("1 | 2 ^ 3 & 4"), "((1)|((2)^((3)&(4))))");
+        assert_eq!(parens::<i32>("2 ** 3 ^ 1"), "(((2)**(3))^(1))");
+        assert_eq!(parser::parse::<i32>("x ** 2").map(|e| e.to_string()).ok(), Some(String::from("x ** 2")));
     }
 
-    fn terminal<T: TBound<T>>(s: &str) -> (Option<Expr<T>>, &str) {
-        let s = s.trim_start();
-        if s.is_empty() {
-            return (None, s);
+    #[test]
+    fn bitwise_precedence() {
+        assert_eq!(parens::<i32>("x & 1 == 0"), "((x)&((1)==(0)))");
+        assert_eq!(eval::<i32>("let x = 3; (x & 1) == 1"), Ok(String::from("true")));
+        assert!(eval::<i32>("let x = 3; x & 1 == 1").is_err());
+        assert_eq!(parens::<i32>("1 << 2 + 3"), "((1)<<((2)+(3)))");
+        assert_eq!(parens::<i32>("1 << 2 < 3"), "(((1)<<(2))<(3))");
+    }
+
+    #[test]
+    fn invalid_shifts() {
+        assert_eq!(eval::<i32>("1 << 31"), Ok(String::from("-2147483648")));
+        assert_eq!(eval::<i32>("1 << 32"), Err(String::from("invalid argument of <<")));
+        assert_eq!(eval::<i32>("1 << -1"), Err(String::from("invalid argument of <<")));
+        assert_eq!(eval::<u32>("1 >> 32"), Err(String::from("invalid argument of >>")));
+    }
+
+    #[test]
+    fn typed_errors() {
+        let at = |s: &str| {
+            let e = parser::parse::<u32>(s).unwrap();
+            e.eval_with(&Env::default()).map(|v| v.to_string()).map_err(|e| e.to_string())
+        };
+        assert_eq!(at("2 * (1 / (3 - 2))"), Ok(String::from("2")));
+        assert_eq!(at("2 * (1 / (3 - 3))"), Err(String::from("division by zero in 1 / (3 - 3)")));
+        assert_eq!(at("1 + -1"), Err(String::from("operator unary - not supported for type u32 in -1")));
+        assert_eq!(at("4294967295 * 2"), Err(String::from("arithmetic overflow in 4294967295 * 2")));
+        assert_eq!(at("1 + y"), Err(String::from("unknown variable y in y")));
+        assert_eq!(at("(1 < 2) < 3"), Err(String::from("operator < not supported for type bool in 1 < 2 < 3")));
+    }
+
+    #[test]
+    fn function_calls() {
+        assert_eq!(eval::<i32>("abs(-3) + min(4, 2) * max(1, 5)"), Ok(String::from("13")));
+        assert_eq!(eval::<i32>("pow(2, 10)"), Ok(String::from("1024")));
+        assert_eq!(eval::<f64>("sin(0) + cos(0) + sqrt(4)"), Ok(String::from("3")));
+        assert_eq!(eval::<f64>("atan2(1, 1) * 4"), Ok(String::from("3.141592653589793")));
+        assert_eq!(eval::<i32>("sqrt(4)"), Err(String::from("unknown function sqrt")));
+        assert_eq!(eval::<f64>("len(1)"), Err(String::from("unknown function len")));
+        assert_eq!(eval::<i32>("min(1)"), Err(String::from("function min expects 2 arguments, got 1")));
+        assert_eq!(eval::<f64>("sqrt(-1)"), Err(String::from("invalid argument of sqrt")));
+    }
+
+    #[test]
+    fn string_operators() {
+        let s = |s: &str| eval::<String>(s);
+        assert_eq!(s("\"a\" + \"b c\""), Ok(String::from("ab c")));
+        assert_eq!(s("\"ab\" * 3"), Ok(String::from("ababab")));
+        assert_eq!(s("\"banana\" - \"an\""), Ok(String::from("ba")));
+        assert_eq!(s("len(\"h\\u{e9}\")"), Ok(String::from("2")));
+        assert_eq!(s("\"a\\\"\\n\""), Ok(String::from("a\"\n")));
+        assert_eq!(s("12 + 3"), Ok(String::from("123")));
+        assert_eq!(s("\"a\" * \"b\""), Err(String::from("operand of * must be an integer, found \"b\"")));
+        assert_eq!(s("\"a\" / \"b\""), Err(String::from("operator / not supported for type str")));
+        assert_eq!(s("\"abc"), Err(String::from("expected value, found '\"abc' at character 0 (byte 0)")));
+        assert_eq!(s("\"a\\q\""), Err(String::from("expected value, found '\"a\\q\"' at character 0 (byte 0)")));
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let min = "(-2147483647 - 1)";
+        let i32_cases = [
+            (String::from("2147483647 + 1"), "-2147483648", "2147483647"),
+            (format!("{min} - 1"), "2147483647", "-2147483648"),
+            (format!("-{min}"), "-2147483648", "2147483647"),
+            (format!("{min} / -1"), "-2147483648", "2147483647"),
+            (String::from("65536 * 65536"), "0", "2147483647"),
+            (String::from("-2 ** 31 * 2"), "0", "-2147483648"),
+        ];
+        let u32_cases = [
+            (String::from("0 - 1"), "4294967295", "0"),
+            (String::from("1 - 2 + 3"), "2", "3"),
+            (String::from("4294967295 + 1"), "0", "4294967295"),
+            (String::from("2 ** 32"), "0", "4294967295"),
+        ];
+        for arith in [Arith::Checked, Arith::Wrapping, Arith::Saturating] {
+            set_arith(arith);
+            let expected = |wrapping: &str, saturating: &str| match arith {
+                Arith::Checked => Err(String::from("arithmetic overflow")),
+                Arith::Wrapping => Ok(wrapping.to_string()),
+                Arith::Saturating => Ok(saturating.to_string()),
+            };
+            for (s, wrapping, saturating) in &i32_cases {
+                assert_eq!(eval::<i32>(s), expected(wrapping, saturating), "{s}");
+            }
+            for (s, wrapping, saturating) in &u32_cases {
+                assert_eq!(eval::<u32>(s), expected(wrapping, saturating), "{s}");
+            }
+            // Policies do not apply to errors other than overflow
+            assert_eq!(eval::<i32>("1 / 0"), Err(String::from("division by zero")));
+            assert_eq!(eval::<i32>(&format!("{min} % -1")), Ok(String::from("0")));
         }
-        let i = s.find(T::pattern).unwrap_or(s.len());
-        if i == 0 {
-            return (None, s)
+        set_arith(Arith::Checked);
+    }
+
+    #[test]
+    fn operator_entries() {
+        use expr::{Notation, Op1Kind, Op2Kind, OPERATORS};
+        fn check<T: TBound<T>>() {
+            for d in OPERATORS.iter().filter(|d| d.applies::<T>()) {
+                let first = match d.notation {
+                    Notation::Prefix(op) | Notation::Postfix(op) => op.def(),
+                    Notation::Infix(op, _) => op.def::<T>(),
+                };
+                assert!(first.notation == d.notation && first.prec == d.prec, "{}", d.symbol);
+                assert_eq!(first.name, d.name, "{}", d.symbol);
+            }
+            for op in Op2Kind::ALL {
+                assert!(matches!(op.def::<T>().notation, Notation::Infix(o, _) if o == op));
+            }
         }
-        if let Ok(v) = s[..i].parse::<T>() {
-            (Some(Expr::Value {v}), &s[i..])
-        } else {
-            (None, s)
+        for op in Op1Kind::ALL {
+            assert!(matches!(op.def().notation, Notation::Prefix(o) | Notation::Postfix(o) if o == op));
         }
+        check::<i32>();
+        check::<f64>();
+        assert_eq!(Op1Kind::Minus.symbol(), "-");
+        assert_eq!(Op1Kind::Fact.def().prec, 14);
+        assert_eq!(Op2Kind::Sub.def::<f64>().symbol, "-");
+        assert_eq!(Op2Kind::Sub.def::<f64>().name, "sub");
+        assert_eq!(Op2Kind::Pow.symbol::<f64>(), "^");
+        assert_eq!(Op2Kind::Pow.symbol::<i32>(), "**");
+        assert_eq!(Op2Kind::BitXor.symbol::<i32>(), "^");
+    }
+
+    /// Runs `f` on a thread with the stack of evaluation
+    pub fn with_stack<R: Send + 'static>(f: impl FnOnce() -> R + Send + 'static) -> R {
+        thread::Builder::new().stack_size(STACK_SIZE).spawn(f).unwrap().join().unwrap()
     }
 
-    pub trait TerminalEnd {
-        fn pattern(c: char) -> bool;
+    #[test]
+    fn nesting_limit() {
+        let body = format!("{}f(n - 1){}", "0 + (".repeat(400), ")".repeat(400));
+        let script = format!("fn f(n) = n == 0 ? 0 : {body}; f(1000)");
+        let r = with_stack(move || {
+            expr::set_max_depth(expr::MAX_DEPTH_LIMIT);
+            eval::<i32>(&script)
+        });
+        assert_eq!(r, Err(format!("more than {} nested subexpressions in evaluation", expr::MAX_NESTING)));
+        let r = with_stack(|| {
+            expr::set_max_depth(expr::MAX_DEPTH_LIMIT);
+            eval::<i32>("fn f(n) = n == 0 ? 0 : f(n - 1); f(9999)")
+        });
+        assert_eq!(r, Ok(String::from("0")));
     }
-    fn pattern_int(c: char) -> bool {
-        c >= '0' && c <= '9'
+
+    #[test]
+    fn recursion_limit() {
+        let r = with_stack(|| eval::<i32>("fn f(n) = n == 0 ? 0 : f(n - 1); f(1000)"));
+        assert_eq!(r, Err(String::from("more than 1000 nested calls of user functions")));
     }
-    impl TerminalEnd for i32 {
-        fn pattern(c: char) -> bool {
-            !pattern_int(c)
+
+    #[test]
+    fn parse_nesting_limit() {
+        const MAX: usize = expr::MAX_NESTING;
+        fn error(s: &str) -> (&'static str, usize) {
+            parser::parse::<i32>(s).map(|_| ("", 0)).unwrap_or_else(|e| (e.expected, e.pos))
         }
+        with_stack(|| {
+            let nested = ("less deeply nested expression", MAX);
+            assert!(parser::parse::<i32>(&format!("{}x", "-".repeat(MAX - 1))).is_ok());
+            assert_eq!(error(&format!("{}x", "-".repeat(MAX))), nested);
+            assert_eq!(error(&"(".repeat(1_000_000)), nested);
+            assert_eq!(error(&format!("{}1", "2 ** ".repeat(MAX))), ("less deeply nested expression", MAX * 5));
+            let sum = format!("{}1", "1+".repeat(MAX - 1));
+            assert_eq!(eval::<i32>(&sum), Ok(MAX.to_string()));
+            assert_eq!(error(&format!("1+{sum}")), ("less deeply nested expression", 2 * MAX + 1));
+            assert_eq!(error(&format!("f({sum})")), ("less deeply nested expression", 2 * MAX + 2));
+        });
     }
-    impl TerminalEnd for u32 {
-        fn pattern(c: char) -> bool {
-            !pattern_int(c)
+
+    /// Checks that each statement of `script` has the same result in the tree walking interpreter
+    /// and in the stack machine
+    fn same_in_vm<T: TBound<T>>(script: &str) {
+        fn show<T: Display>(r: Result<Option<Value<T>>, ExprError>) -> String {
+            match r {
+                Ok(v) => v.map(|v| v.to_string()).unwrap_or_default(),
+                Err(e) => e.to_string(),
+            }
+        }
+        let (mut tree, mut vm) = (Env::default(), Env::default());
+        for stmt in parser::parse_script::<T>(script).unwrap_or_else(|e| panic!("{e}")) {
+            assert_eq!(show(stmt.exec(&mut tree)), show(vm::exec(&stmt, &mut vm)), "{stmt}");
         }
     }
-    impl TerminalEnd for f64 {
-        fn pattern(c: char) -> bool {
-            !(pattern_int(c) || c == '.')
+
+    #[test]
+    fn stack_machine() {
+        same_in_vm::<i32>("let x = 3; x * 2 - 1; y + 1; 1 / (x - 3); x > 2 && 1 / 0 == 1; x < 2 && 1 / 0 == 1");
+        same_in_vm::<i32>("let x = -2; x < 0 ? -x : x; abs(x < 0); x || 1; max(x, 7) % 4");
+        same_in_vm::<f64>("fn f(a, b) = a < b ? b : a; f(1, 2) + f(3); g(1); f(1 < 2, 3)");
+        with_stack(|| {
+            same_in_vm::<f64>("fn f(n) = n == 0 ? 0 : f(n - 1); f(999); f(1000)");
+            let body = format!("{}f(n - 1){}", "0 + (".repeat(400), ")".repeat(400));
+            expr::set_max_depth(expr::MAX_DEPTH_LIMIT);
+            same_in_vm::<i32>(&format!("fn f(n) = n == 0 ? 0 : {body}; f(1000); f(10)"));
+            let mut e = Expr::Var {name: String::from("x")};
+            for _ in 0..60_000 {
+                e = Expr::Op1 {op: expr::Op1Kind::Minus, child: Box::new(e)};
+            }
+            let mut env = Env::default();
+            env.set("x", Value::Val(1));
+            let tree = e.eval_with(&env).map(|v| v.to_string()).map_err(|e| e.error.to_string());
+            let p = vm::Program::compile(&e);
+            let vm = p.run(&p.bind(&env), &env).map(|v| v.to_string()).map_err(|e| e.error.to_string());
+            assert_eq!(tree, vm);
+            assert_eq!(vm.err(), Some(format!("more than {} nested subexpressions in evaluation", expr::MAX_NESTING)));
+        });
+    }
+
+    /// Whether `p` parsed back from the display of `e` is structurally equal to `e`. A value is
+    /// displayed as a literal that may parse as an operation, like `-1`, `1/3` or `1+2i`, so a
+    /// value matches an operation of values that evaluates to the same value. Values are compared
+    /// by text, as a value like NaN is unequal to itself.
+    fn same<T: TBound<T>>(e: &Expr<T>, p: &Expr<T>) -> bool {
+        fn literal<T>(p: &Expr<T>) -> bool {
+            match p {
+                Expr::Value {..} => true,
+                Expr::Op1 {child, ..} => literal(child),
+                Expr::Op2 {l, r, ..} => literal(l) && literal(r),
+                _ => false,
+            }
+        }
+        match (e, p) {
+            (Expr::Value {v}, p) => {
+                let text = Value::Val(v.clone()).to_string();
+                literal(p) && p.eval_with(&Env::default()).is_ok_and(|w| w.to_string() == text)
+            }
+            (Expr::Var {name: a}, Expr::Var {name: b}) => a == b,
+            (Expr::Op1 {op: a, child: x}, Expr::Op1 {op: b, child: y}) => a == b && same(x, y),
+            (Expr::Op2 {op: a, l: xl, r: xr}, Expr::Op2 {op: b, l: yl, r: yr}) => {
+                a == b && same(xl, yl) && same(xr, yr)
+            }
+            (Expr::Call {name: a, args: x}, Expr::Call {name: b, args: y}) => {
+                a == b && x.len() == y.len() && x.iter().zip(y).all(|(x, y)| same(x, y))
+            }
+            (Expr::Cond {c: xc, t: xt, f: xf}, Expr::Cond {c: yc, t: yt, f: yf}) => {
+                same(xc, yc) && same(xt, yt) && same(xf, yf)
+            }
+            _ => false,
         }
     }
-    impl TerminalEnd for String {
-        fn pattern(c: char) -> bool {
-            c == ' '
+
+    /// Checks that expressions of `corpus` and their simplified forms are displayed in each
+    /// representation as a text that parses back to a structurally equal expression
+    fn round_trip<T: TBound<T>>(corpus: &[&str]) {
+        for s in corpus {
+            let e = parser::parse::<T>(s).unwrap_or_else(|e| panic!("{s}: {e}"));
+            for e in [e.clone(), e.simplify()] {
+                for r in [serial::Repr::Infix, serial::Repr::Json, serial::Repr::Sexpr] {
+                    for full in [false, true] {
+                        let t = r.write(&e, full);
+                        let p = r.parse::<T>(&t).unwrap_or_else(|e| panic!("{s}: {t}: {e}"));
+                        assert!(same(&e, &p), "{s}: {t}: {}", r.write(&p, true));
+                    }
+                }
+            }
         }
     }
+
+    /// Expressions valid in each type
+    const CORPUS: &[&str] = &[
+        "1", "x", "-x", "--x", "-(-1)", "x + 0", "0 + x", "x - 0", "x * 1", "x ^ 1", "x - x", "x * 0",
+        "1 + 2 * 3", "(1 + 2) * 3", "1 - (2 - 3)", "(1 - 2) - 3", "x / (y * z)", "-x ^ 2", "(-x) ^ 2",
+        "x ^ y ^ z", "(x ^ y) ^ z", "2 ^ -x", "x == y", "!(x < y)", "x < y && y <= z || x != z",
+        "x > 0 ? x : -x", "x ? y : z ? 1 : 2", "(x ? y : z) ? 1 : 2", "min(x, 1) + max(2, y)", "abs(-x)",
+        "x in y", "(x in y) == (y in x)",
+    ];
+
+    #[test]
+    fn round_trip_integers() {
+        let corpus = [
+            "7 % 3", "-(2147483647) - 1", "2147483647 + 1", "x & 3 | y", "~x << 2 >> 1", "x ^ 5 ** 2", "5!",
+            "0x1f + 0b101 + 0o17", "1 / 0",
+        ];
+        let tree = |s| parser::parse::<i32>(s).unwrap();
+        assert!(same(&tree("x - 3"), &tree("x - (1 + 2)")) && !same(&tree("x - (1 - 2)"), &tree("x - 1 - 2")));
+        round_trip::<i32>(CORPUS);
+        round_trip::<i32>(&corpus);
+        round_trip::<u32>(CORPUS);
+        round_trip::<u32>(&corpus);
+        round_trip::<bigint::BigInt>(CORPUS);
+        round_trip::<bigint::BigInt>(&["7 % 3", "-(2147483647) - 1", "2 ^ 100", "-(2 ^ 100)", "25!"]);
+    }
+
+    #[test]
+    fn round_trip_reals() {
+        let corpus = [
+            "1.5", "0.1 + 0.2", "-0.0", "0 / 0", "1 / 0", "-1 / 0", "1e300 * 1e10", "1e-300 / 1e10", "sqrt(2)",
+            "x + 0.5 * y", "pi", "2.5e-8",
+        ];
+        round_trip::<f64>(CORPUS);
+        round_trip::<f64>(&corpus);
+        round_trip::<complex::Complex>(CORPUS);
+        round_trip::<complex::Complex>(&corpus);
+        round_trip::<complex::Complex>(&["i", "1 + 2i", "-i", "(1 - 2i) * (3 + i)", "sqrt(-1)"]);
+        round_trip::<interval::Interval>(CORPUS);
+        round_trip::<interval::Interval>(&corpus);
+        round_trip::<interval::Interval>(&["[1, 2]", "[-1, 2] * [3, 4]", "1.05±0.05", "1 / 3", "[1, 2] - [1, 2]"]);
+    }
+
+    #[test]
+    fn round_trip_exact() {
+        let corpus = ["1.5", "1 / 3", "-1 / 3", "x / 3", "0.25 * x", "2 / 3 ^ 2", "(1 / 3) ^ 2", "-(1 / 3) ^ 2"];
+        round_trip::<rational::Rational>(CORPUS);
+        round_trip::<rational::Rational>(&corpus);
+        round_trip::<decimal::Decimal>(CORPUS);
+        round_trip::<decimal::Decimal>(&corpus);
+    }
+
+    #[test]
+    fn round_trip_others() {
+        round_trip::<String>(&["\"a\"", "x", "\"a\" + x", "\"a\\\"b\" == x", "x ? \"\" : \"\\n\""]);
+        round_trip::<quantity::Quantity>(CORPUS);
+        round_trip::<quantity::Quantity>(&["1 m", "2 m / 4 s", "(1 m) ^ 2", "x * 3 kg", "-1 m"]);
+    }
 }
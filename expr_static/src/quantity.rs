@@ -0,0 +1,347 @@
+use crate::ops::{EvalError, Func, Funcs, Ops};
+use crate::lexer::{float_literal, parse_float, TerminalEnd};
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// Exponents of SI base dimensions, in the order of `BASE`
+type Dim = [i8; 7];
+
+/// Symbols of SI base units
+const BASE: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+/// Units recognized in literals: symbol, size in SI base units, and dimension
+const UNITS: &[(&str, f64, Dim)] = &[
+    ("m", 1.0, [1, 0, 0, 0, 0, 0, 0]),
+    ("km", 1000.0, [1, 0, 0, 0, 0, 0, 0]),
+    ("cm", 0.01, [1, 0, 0, 0, 0, 0, 0]),
+    ("mm", 0.001, [1, 0, 0, 0, 0, 0, 0]),
+    ("g", 0.001, [0, 1, 0, 0, 0, 0, 0]),
+    ("kg", 1.0, [0, 1, 0, 0, 0, 0, 0]),
+    ("t", 1000.0, [0, 1, 0, 0, 0, 0, 0]),
+    ("s", 1.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("ms", 0.001, [0, 0, 1, 0, 0, 0, 0]),
+    ("min", 60.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("h", 3600.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0, 0]),
+    ("K", 1.0, [0, 0, 0, 0, 1, 0, 0]),
+    ("mol", 1.0, [0, 0, 0, 0, 0, 1, 0]),
+    ("cd", 1.0, [0, 0, 0, 0, 0, 0, 1]),
+    ("L", 0.001, [3, 0, 0, 0, 0, 0, 0]),
+    ("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0]),
+    ("N", 1.0, [1, 1, -2, 0, 0, 0, 0]),
+    ("Pa", 1.0, [-1, 1, -2, 0, 0, 0, 0]),
+    ("J", 1.0, [2, 1, -2, 0, 0, 0, 0]),
+    ("W", 1.0, [2, 1, -3, 0, 0, 0, 0]),
+    ("C", 1.0, [0, 0, 1, 1, 0, 0, 0]),
+    ("V", 1.0, [2, 1, -3, -1, 0, 0, 0]),
+];
+
+const DIMENSIONLESS: Dim = [0; 7];
+
+/// A unit of measurement, the size is the ratio `num / den` of SI base units
+#[derive(Clone, PartialEq)]
+struct Unit {
+    name: String,
+    num: f64,
+    den: f64,
+    dim: Dim,
+}
+
+impl Default for Unit {
+    fn default() -> Unit {
+        Unit::si(DIMENSIONLESS)
+    }
+}
+
+impl Unit {
+    /// The coherent SI unit of dimension `dim`, named by base units like `m*kg/s^2`
+    fn si(dim: Dim) -> Unit {
+        let term = |(name, e): (&str, i8)| if e == 1 { name.to_string() } else { format!("{name}^{e}") };
+        let terms = |pos: bool| BASE.iter().zip(dim)
+            .filter(|&(_, e)| if pos { e > 0 } else { e < 0 })
+            .map(|(name, e)| term((name, if pos { e } else { -e })))
+            .collect::<Vec<_>>();
+        let (num, den) = (terms(true), terms(false));
+        let name = if num.is_empty() {
+            BASE.iter().zip(dim).filter(|&(_, e)| e != 0).map(|(name, e)| term((name, e))).collect::<Vec<_>>().join("*")
+        } else if den.is_empty() {
+            num.join("*")
+        } else {
+            format!("{}/{}", num.join("*"), den.join("/"))
+        };
+        Unit {name, num: 1.0, den: 1.0, dim}
+    }
+
+    fn is_dimensionless(&self) -> bool {
+        self.dim == DIMENSIONLESS
+    }
+
+    /// Combines the unit with `r` raised to power `e`, for a product or a quotient of units
+    fn combine(&mut self, r: &Unit, e: i8) -> Option<()> {
+        for (d, rd) in self.dim.iter_mut().zip(r.dim) {
+            *d = d.checked_add(rd.checked_mul(e)?)?;
+        }
+        let (n, d) = (r.num.powi(e.abs().into()), r.den.powi(e.abs().into()));
+        if e > 0 {
+            (self.num, self.den) = (self.num * n, self.den * d);
+        } else {
+            (self.num, self.den) = (self.num * d, self.den * n);
+        }
+        Some(())
+    }
+}
+
+/// Length in bytes of a unit at the start of `s`, like `km/h` or `m*kg/s^2`, and the unit
+fn parse_unit(s: &str) -> Option<(usize, Unit)> {
+    let mut unit: Option<Unit> = None;
+    // End of the unit parsed so far, and position of the next term
+    let (mut end, mut i) = (0, 0);
+    let mut sign = 1;
+    loop {
+        let rest = &s[i..];
+        let n = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let Some(&(name, size, dim)) = UNITS.iter().find(|u| u.0 == &rest[..n]) else {
+            break;
+        };
+        // A name followed by a digit or `_` is an identifier, followed by `(` a function call
+        if rest[n..].starts_with(|c: char| c.is_ascii_digit() || c == '_' || c == '(') {
+            break;
+        }
+        let mut len = n;
+        let mut e: i8 = 1;
+        if let Some(p) = rest[n..].strip_prefix('^') {
+            let minus = p.starts_with('-') as usize;
+            let d = minus + p[minus..].find(|c: char| !c.is_ascii_digit()).unwrap_or(p.len() - minus);
+            match p[..d].parse::<i8>() {
+                Ok(v) => e = v,
+                Err(_) => break,
+            }
+            len += 1 + d;
+        }
+        let term = Unit {name: name.to_string(), num: size, den: 1.0, dim};
+        let mut u = unit.clone().unwrap_or_default();
+        u.combine(&term, e.checked_mul(sign)?)?;
+        unit = Some(u);
+        end = i + len;
+        sign = match s[end..].chars().next() {
+            Some('*') => 1,
+            Some('/') => -1,
+            _ => break,
+        };
+        i = end + 1;
+    }
+    let mut unit = unit?;
+    unit.name = s[..end].to_string();
+    Some((end, unit))
+}
+
+/// A physical quantity, a number of units
+#[derive(Clone, Default)]
+pub struct Quantity {
+    v: f64,
+    unit: Unit,
+}
+
+impl Quantity {
+    fn new(v: f64, unit: Unit) -> Quantity {
+        Quantity {v, unit}
+    }
+
+    /// The value in SI base units
+    fn si(&self) -> f64 {
+        self.v * self.unit.num / self.unit.den
+    }
+
+    fn incompatible(&self, op: &'static str, r: &Quantity) -> EvalError {
+        let name = |u: &Unit| if u.is_dimensionless() { "1".to_string() } else { Unit::si(u.dim).name };
+        EvalError::IncompatibleDimensions {op, l: name(&self.unit), r: name(&r.unit)}
+    }
+
+    /// Converts `r` to the unit of `self`, for operands of `op` of the same dimension
+    fn same_unit(&self, op: &'static str, r: &Quantity) -> Result<f64, EvalError> {
+        if self.unit.dim != r.unit.dim {
+            return Err(self.incompatible(op, r));
+        }
+        if self.unit == r.unit {
+            return Ok(r.v);
+        }
+        Ok(r.si() * self.unit.den / self.unit.num)
+    }
+
+    /// A product or a quotient, `e` is 1 or -1. A dimensionless operand keeps the unit of the
+    /// other one, otherwise the result is in SI units.
+    fn combine(&self, r: &Quantity, e: i8, v: f64) -> Result<Quantity, EvalError> {
+        if r.unit.is_dimensionless() && r.unit.num == r.unit.den {
+            return Ok(Quantity::new(v, self.unit.clone()));
+        }
+        if e == 1 && self.unit.is_dimensionless() && self.unit.num == self.unit.den {
+            return Ok(Quantity::new(v, r.unit.clone()));
+        }
+        let mut unit = self.unit.clone();
+        unit.combine(&r.unit, e).ok_or(EvalError::Overflow)?;
+        let si = Unit::si(unit.dim);
+        Ok(Quantity::new(v * unit.num / unit.den, si))
+    }
+
+    /// The quantity in SI units raised to an integer power
+    fn powi(&self, e: i8) -> Result<Quantity, EvalError> {
+        let mut dim = DIMENSIONLESS;
+        for (d, sd) in dim.iter_mut().zip(self.unit.dim) {
+            *d = sd.checked_mul(e).ok_or(EvalError::Overflow)?;
+        }
+        Ok(Quantity::new(Ops::pow(&self.si(), &e.into())?, Unit::si(dim)))
+    }
+
+    fn sqrt(&self) -> Result<Quantity, EvalError> {
+        if self.unit.dim.iter().any(|d| d % 2 != 0) {
+            return Err(EvalError::InvalidArgument("sqrt"));
+        }
+        let v = self.si();
+        if v < 0.0 {
+            return Err(EvalError::InvalidArgument("sqrt"));
+        }
+        Ok(Quantity::new(v.sqrt(), Unit::si(self.unit.dim.map(|d| d / 2))))
+    }
+}
+
+/// Quantities are equal if they are the same amount, in any units
+impl PartialEq for Quantity {
+    fn eq(&self, r: &Quantity) -> bool {
+        self.unit.dim == r.unit.dim && self.si() == r.si()
+    }
+}
+
+impl Display for Quantity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        if self.unit.name.is_empty() {
+            write!(f, "{}", self.v)
+        } else {
+            write!(f, "{} {}", self.v, self.unit.name)
+        }
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = ();
+    /// Parses a number with an optional unit `2.5 km/h`, or a unit alone `km`, meaning one unit
+    fn from_str(s: &str) -> Result<Quantity, ()> {
+        let sign = usize::from(s.starts_with(['+', '-']));
+        let n = sign + float_literal(&s[sign..]);
+        let v = if n == 0 { 1.0 } else { parse_float(&s[..n]).ok_or(())? };
+        let u = s[n..].trim_start();
+        if u.is_empty() {
+            return Ok(Quantity::new(v, Unit::default()));
+        }
+        match parse_unit(u) {
+            Some((len, unit)) if len == u.len() => Ok(Quantity::new(v, unit)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Ops<Quantity> for Quantity {
+    const TYPE: &'static str = "quantity";
+    fn one() -> Option<Quantity> {
+        Some(Quantity::new(1.0, Unit::default()))
+    }
+    fn neg_involutive() -> bool {
+        true
+    }
+    fn neg(&self) -> Result<Quantity, EvalError> {
+        Ok(Quantity::new(-self.v, self.unit.clone()))
+    }
+    /// Sum in the unit of the left operand
+    fn add(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        Ok(Quantity::new(Ops::add(&self.v, &self.same_unit("+", r)?)?, self.unit.clone()))
+    }
+    fn sub(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        Ok(Quantity::new(Ops::sub(&self.v, &self.same_unit("-", r)?)?, self.unit.clone()))
+    }
+    fn mul(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        self.combine(r, 1, Ops::mul(&self.v, &r.v)?)
+    }
+    fn div(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        self.combine(r, -1, Ops::div(&self.v, &r.v)?)
+    }
+    fn rem(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        Ok(Quantity::new(Ops::rem(&self.v, &self.same_unit("%", r)?)?, self.unit.clone()))
+    }
+    /// Power with a dimensionless exponent, which must be an integer for a dimensioned base
+    fn pow(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        if !r.unit.is_dimensionless() {
+            return Err(Quantity::one().unwrap().incompatible("^", r));
+        }
+        let e = r.si();
+        if self.unit.is_dimensionless() {
+            return Ok(Quantity::new(Ops::pow(&self.si(), &e)?, Unit::default()));
+        }
+        if e.fract() != 0.0 || e.abs() > i8::MAX as f64 {
+            return Err(EvalError::InvalidArgument("^"));
+        }
+        self.powi(e as i8)
+    }
+    fn compare(&self, r: &Quantity) -> Result<Option<Ordering>, EvalError> {
+        self.v.compare(&self.same_unit("comparison", r)?)
+    }
+    /// The quantity in the unit of `r`, ignoring the number of `r`
+    fn convert(&self, r: &Quantity) -> Result<Quantity, EvalError> {
+        if self.unit.dim != r.unit.dim {
+            return Err(self.incompatible("in", r));
+        }
+        Ok(Quantity::new(self.si() * r.unit.den / r.unit.num, r.unit.clone()))
+    }
+}
+
+impl Funcs for Quantity {
+    const FUNCS: &'static [Func<Quantity>] = &[
+        Func {name: "abs", arity: 1, f: |a| Ok(Quantity::new(a[0].v.abs(), a[0].unit.clone()))},
+        Func {name: "min", arity: 2, f: |a| Ok(if a[1].compare(&a[0])? == Some(Ordering::Less) { &a[1] } else { &a[0] }.clone())},
+        Func {name: "max", arity: 2, f: |a| Ok(if a[1].compare(&a[0])? == Some(Ordering::Greater) { &a[1] } else { &a[0] }.clone())},
+        Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        Func {name: "sqrt", arity: 1, f: |a| a[0].sqrt()},
+    ];
+}
+
+impl TerminalEnd for Quantity {
+    fn pattern(c: char) -> bool {
+        !(c.is_ascii_digit() || c == '.')
+    }
+    /// A literal is a number optionally followed by spaces and a unit, or a unit alone
+    fn literal(s: &str) -> usize {
+        let n = float_literal(s);
+        let u = if n > 0 { s[n..].trim_start_matches(' ') } else { s };
+        match parse_unit(u) {
+            Some((len, _)) => s.len() - u.len() + len,
+            None => n,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Quantity;
+    use crate::tests::eval;
+
+    fn q(s: &str) -> Result<String, String> {
+        eval::<Quantity>(s)
+    }
+
+    #[test]
+    fn dimensions() {
+        assert_eq!(q("3 m + 2 m"), Ok(String::from("5 m")));
+        assert_eq!(q("10 s * 3 m"), Ok(String::from("30 m*s")));
+        assert_eq!(q("(2 m) ^ 2"), Ok(String::from("4 m^2")));
+        assert_eq!(q("1 m < 2 m"), Ok(String::from("true")));
+        assert_eq!(q("1 m + 1 s"), Err(String::from("operands of + have incompatible dimensions m and s")));
+        assert_eq!(q("1 m < 2 s"), Err(String::from("operands of comparison have incompatible dimensions m and s")));
+        assert_eq!(q("1 m ^ 0.5"), Err(String::from("invalid argument of ^")));
+    }
+
+    #[test]
+    fn conversion() {
+        assert_eq!(q("1 km in m"), Ok(String::from("1000 m")));
+        assert_eq!(q("2.5 km / 1 h"), Ok(String::from("0.6944444444444444 m/s")));
+        assert_eq!(q("1 m in s"), Err(String::from("operands of in have incompatible dimensions m and s")));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::bigint::BigInt;
+use crate::ops::{EvalError, Func, Funcs, Ops};
+use crate::lexer::TerminalEnd;
+use std::cmp::Ordering;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// An exact rational number
+#[derive(Clone, PartialEq, Eq)]
+pub struct Rational {
+    /// Numerator, without common factors with the denominator
+    num: BigInt,
+    /// Denominator, always positive
+    den: BigInt,
+}
+
+impl Default for Rational {
+    fn default() -> Rational {
+        Rational::int(BigInt::default())
+    }
+}
+
+impl Rational {
+    fn int(num: BigInt) -> Rational {
+        Rational {num, den: BigInt::from_u64(1)}
+    }
+
+    /// Creates the rational number `num / den` in lowest terms
+    fn new(num: BigInt, den: BigInt) -> Result<Rational, EvalError> {
+        if den.is_zero() {
+            return Err(EvalError::DivisionByZero);
+        }
+        let g = num.gcd(&den);
+        let (mut num, mut den) = (num.div(&g)?, den.div(&g)?);
+        if den.is_negative() {
+            (num, den) = (num.neg()?, den.neg()?);
+        }
+        Ok(Rational {num, den})
+    }
+
+    fn is_int(&self) -> bool {
+        self.den == BigInt::from_u64(1)
+    }
+
+    /// The largest integer not greater than the number
+    fn floor(&self) -> Result<BigInt, EvalError> {
+        let (q, r) = self.num.divrem(&self.den)?;
+        if r.is_negative() {
+            q.sub(&BigInt::from_u64(1))
+        } else {
+            Ok(q)
+        }
+    }
+
+    fn ceil(&self) -> Result<BigInt, EvalError> {
+        Rational {num: self.num.neg()?, den: self.den.clone()}.floor()?.neg()
+    }
+}
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        if self.is_int() {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+/// Parses a number with an optional fractional part, like `12.5`
+fn decimal(s: &str) -> Result<Rational, ()> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if int.is_empty() && frac.is_empty() || frac.starts_with(['+', '-']) {
+        return Err(());
+    }
+    let num = format!("{int}{frac}").parse::<BigInt>()?;
+    let den = BigInt::from_u64(10).pow(&BigInt::from_u64(frac.len() as u64)).map_err(|_| ())?;
+    Rational::new(num, den).map_err(|_| ())
+}
+
+impl FromStr for Rational {
+    type Err = ();
+    /// Parses an integer `3`, a decimal number `0.25`, or a fraction `3/4` like the display
+    /// of a value
+    fn from_str(s: &str) -> Result<Rational, ()> {
+        match s.split_once('/') {
+            Some((n, d)) => {
+                let (n, d) = (decimal(n)?, decimal(d)?);
+                n.div(&d).map_err(|_| ())
+            }
+            None => decimal(s),
+        }
+    }
+}
+
+impl Ops<Rational> for Rational {
+    const TYPE: &'static str = "rational";
+    fn zero() -> Option<Rational> {
+        Some(Rational::default())
+    }
+    fn one() -> Option<Rational> {
+        Some(Rational::int(BigInt::from_u64(1)))
+    }
+    fn annihilates() -> bool {
+        true
+    }
+    fn neg_involutive() -> bool {
+        true
+    }
+    fn neg(&self) -> Result<Rational, EvalError> {
+        Ok(Rational {num: self.num.neg()?, den: self.den.clone()})
+    }
+    fn add(&self, r: &Rational) -> Result<Rational, EvalError> {
+        Rational::new(self.num.mul(&r.den)?.add(&r.num.mul(&self.den)?)?, self.den.mul(&r.den)?)
+    }
+    fn sub(&self, r: &Rational) -> Result<Rational, EvalError> {
+        self.add(&r.neg()?)
+    }
+    fn mul(&self, r: &Rational) -> Result<Rational, EvalError> {
+        Rational::new(self.num.mul(&r.num)?, self.den.mul(&r.den)?)
+    }
+    fn div(&self, r: &Rational) -> Result<Rational, EvalError> {
+        Rational::new(self.num.mul(&r.den)?, self.den.mul(&r.num)?)
+    }
+    /// Remainder of division with the quotient rounded toward zero, like for integers
+    fn rem(&self, r: &Rational) -> Result<Rational, EvalError> {
+        let q = self.div(r)?;
+        let q = q.num.divrem(&q.den)?.0;
+        self.sub(&r.mul(&Rational::int(q))?)
+    }
+    /// Power with an integer exponent
+    fn pow(&self, r: &Rational) -> Result<Rational, EvalError> {
+        if !r.is_int() {
+            return Err(EvalError::InvalidArgument("^"));
+        }
+        let e = r.num.abs();
+        let p = Rational {num: self.num.pow(&e)?, den: self.den.pow(&e)?};
+        if r.num.is_negative() {
+            Rational::new(p.den, p.num)
+        } else {
+            Ok(p)
+        }
+    }
+    fn compare(&self, r: &Rational) -> Result<Option<Ordering>, EvalError> {
+        Ok(Some(self.num.mul(&r.den)?.cmp(&r.num.mul(&self.den)?)))
+    }
+}
+
+impl Funcs for Rational {
+    const FUNCS: &'static [Func<Rational>] = &[
+        Func {name: "abs", arity: 1, f: |a| Ok(Rational {num: a[0].num.abs(), den: a[0].den.clone()})},
+        Func {name: "min", arity: 2, f: |a| Ok(if a[1].compare(&a[0])? == Some(Ordering::Less) { &a[1] } else { &a[0] }.clone())},
+        Func {name: "max", arity: 2, f: |a| Ok(if a[1].compare(&a[0])? == Some(Ordering::Greater) { &a[1] } else { &a[0] }.clone())},
+        Func {name: "pow", arity: 2, f: |a| Ops::pow(&a[0], &a[1])},
+        Func {name: "num", arity: 1, f: |a| Ok(Rational::int(a[0].num.clone()))},
+        Func {name: "den", arity: 1, f: |a| Ok(Rational::int(a[0].den.clone()))},
+        Func {name: "floor", arity: 1, f: |a| Ok(Rational::int(a[0].floor()?))},
+        Func {name: "ceil", arity: 1, f: |a| Ok(Rational::int(a[0].ceil()?))},
+    ];
+}
+
+impl TerminalEnd for Rational {
+    /// A literal is a decimal number. A fraction like `3/4` is the division of two literals,
+    /// which is exact, so that it binds like any other division.
+    fn pattern(c: char) -> bool {
+        !(c.is_ascii_digit() || c == '.')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rational;
+    use crate::tests::{eval, parens};
+
+    fn q(s: &str) -> Result<String, String> {
+        eval::<Rational>(s)
+    }
+
+    #[test]
+    fn fraction_is_division() {
+        assert_eq!(q("3/4"), Ok(String::from("3/4")));
+        assert_eq!(q("1/3 + 1/6"), Ok(String::from("1/2")));
+        assert_eq!(q("0.5/0.25"), Ok(String::from("2")));
+        assert_eq!(q("-3/4"), Ok(String::from("-3/4")));
+    }
+
+    #[test]
+    fn fraction_precedence() {
+        assert_eq!(parens::<Rational>("2/3^2"), "((2)/((3)^(2)))");
+        assert_eq!(q("2/3^2"), q("2 / 3^2"));
+        assert_eq!(q("2/3^2"), Ok(String::from("2/9")));
+        assert_eq!(q("2^1/2"), Ok(String::from("1")));
+        assert_eq!(q("2^-1/2"), Ok(String::from("1/4")));
+    }
+
+    #[test]
+    fn fraction_value_display() {
+        let simple = |s: &str| crate::parser::parse::<Rational>(s).unwrap().simplify().to_string();
+        assert_eq!(simple("x / (1/3)"), "x / (1/3)");
+        assert_eq!(simple("x ^ (1/3)"), "x ^ (1/3)");
+        assert_eq!(simple("x - -1/3"), "x - -1/3");
+        assert_eq!(simple("x * (-1/3)"), "x * (-1/3)");
+        assert_eq!(simple("-1/3 * x"), "-1/3 * x");
+    }
+}
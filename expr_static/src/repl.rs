@@ -0,0 +1,329 @@
+use crate::{bench, parser, vm, Options, TBound};
+use crate::expr::{Env, Expr, Stmt};
+use crate::ops::Value;
+use crate::serial::Repr;
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+
+/// State kept between lines of a script or an interactive session: variables set by `let`,
+/// and results of statements, which are also variables `$1`, `$2`, ... and `$_` for the last one
+pub struct Session<'a, T, W = io::Stdout> {
+    opts: &'a Options,
+    env: Env<T>,
+    /// Output of results and errors, except for timings of `--bench`
+    out: W,
+    /// Number of results so far
+    results: usize,
+    /// Prefix results with their variable, like `$1 = 3`
+    numbered: bool,
+}
+
+impl<'a, T: TBound<T>> Session<'a, T> {
+    pub fn new(env: Env<T>, opts: &'a Options) -> Session<'a, T> {
+        Session::with_output(env, opts, io::stdout())
+    }
+
+    /// Reads and executes lines from the terminal until the end of input
+    pub fn repl(&mut self) {
+        self.read(io::stdin().lock());
+    }
+}
+
+impl<'a, T: TBound<T>, W: Write> Session<'a, T, W> {
+    pub fn with_output(env: Env<T>, opts: &'a Options, out: W) -> Session<'a, T, W> {
+        Session {opts, env, out, results: 0, numbered: false}
+    }
+
+    /// Executes each line of `input`, an error is reported with its line number if `numbered`,
+    /// and does not stop execution of the following lines
+    pub fn script(&mut self, input: &str, numbered: bool) {
+        for (i, line) in input.lines().enumerate() {
+            let prefix = if numbered { format!("line {}: ", i + 1) } else { String::new() };
+            self.line(line, &prefix);
+        }
+    }
+
+    /// Reads and executes lines of `input` after a prompt each until the end of input
+    pub fn read(&mut self, mut input: impl BufRead) {
+        self.numbered = true;
+        let mut line = String::new();
+        loop {
+            // The prompt is not essential, so an error writing it is ignored
+            let _ = write!(self.out, "> ").and_then(|_| self.out.flush());
+            line.clear();
+            match input.read_line(&mut line) {
+                Ok(0) => {
+                    self.say("");
+                    return;
+                }
+                Ok(_) => self.line(&line, ""),
+                Err(error) => {
+                    self.say(format_args!("Cannot read expression: {error}"));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Writes a line of output
+    fn say(&mut self, text: impl Display) {
+        self.write(format_args!("{text}\n"));
+    }
+
+    /// Writes output, which fails like `print!` if the output is closed
+    fn write(&mut self, text: impl Display) {
+        if let Err(error) = write!(self.out, "{text}") {
+            panic!("cannot write output: {error}");
+        }
+    }
+
+    /// Executes a line of statements separated by `;`, or a single expression in JSON or as an
+    /// S-expression, or a command, `prefix` locates errors
+    fn line(&mut self, line: &str, prefix: &str) {
+        let line = line.trim_end();
+        if line.trim().is_empty() {
+            return;
+        }
+        if let Some(cmd) = line.trim_start().strip_prefix(':') {
+            self.command(cmd, prefix);
+            return;
+        }
+        let stmts = match self.opts.input {
+            Repr::Infix => parser::parse_script::<T>(line),
+            r => r.parse::<T>(line).map(|e| vec![Stmt::Expr(e)]),
+        };
+        let stmts = match stmts {
+            Ok(stmts) => stmts,
+            Err(error) => {
+                self.say(error.caret(line));
+                self.say(format_args!("{prefix}invalid expression: {error}"));
+                return;
+            }
+        };
+        for stmt in stmts {
+            self.statement(stmt, prefix);
+        }
+    }
+
+    fn statement(&mut self, stmt: Stmt<T>, prefix: &str) {
+        if self.opts.dot {
+            self.diagram(stmt, prefix);
+            return;
+        }
+        let simple = stmt.clone().simplify();
+        let (text, simple) = (self.show(&stmt), self.show(&simple));
+        self.say(text);
+        self.say(format_args!("simplified: {simple}"));
+        if let Some(n) = self.opts.bench {
+            if let Stmt::Let{e, ..} | Stmt::Expr(e) = &stmt {
+                bench(e, &self.env, n);
+            }
+        }
+        let v = if self.opts.vm {
+            vm::exec(&stmt, &mut self.env)
+        } else {
+            stmt.exec(&mut self.env)
+        };
+        match v {
+            Ok(Some(v)) => self.result(v),
+            Ok(None) => {}
+            Err(error) => self.say(format_args!("{prefix}no value: {error}")),
+        }
+    }
+
+    /// Displays the tree of a statement as a Graphviz digraph and executes the statement.
+    /// Results are stored but not displayed, and errors are displayed as comments of DOT.
+    fn diagram(&mut self, stmt: Stmt<T>, prefix: &str) {
+        let dot = match &stmt {
+            Stmt::Let{e, ..} | Stmt::Expr(e) if self.opts.dot_eval => e.to_dot_eval(&self.env),
+            Stmt::Let{e, ..} | Stmt::Expr(e) | Stmt::Fn{body: e, ..} => e.to_dot(),
+        };
+        self.write(dot);
+        match stmt.exec(&mut self.env) {
+            Ok(Some(v)) => {
+                self.store(v);
+            }
+            Ok(None) => {}
+            Err(error) => self.say(format_args!("// {prefix}no value: {error}")),
+        }
+    }
+
+    /// Text of a statement with its expression in the output representation
+    fn show(&self, stmt: &Stmt<T>) -> String {
+        let (head, e) = match stmt {
+            Stmt::Let{name, e} => (format!("let {name} = "), e),
+            Stmt::Fn{name, params, body} => (format!("fn {name}({}) = ", params.join(", ")), body),
+            Stmt::Expr(e) => (String::new(), e),
+        };
+        format!("{head}{}", self.opts.output.write(e, self.opts.full_parens))
+    }
+
+    /// Displays the value of a statement and stores it as the next result
+    fn result(&mut self, v: Value<T>) {
+        if self.numbered {
+            self.say(format_args!("${} = {v}", self.results + 1));
+        } else {
+            self.say(&v);
+        }
+        self.store(v);
+    }
+
+    /// Stores the value of a statement as the next result
+    fn store(&mut self, v: Value<T>) {
+        self.results += 1;
+        self.env.set(&format!("${}", self.results), v.clone());
+        self.env.set("$_", v);
+    }
+
+    /// Executes a command `:type [EXPR]`, `:ast EXPR`, `:vars` or `:help`
+    fn command(&mut self, cmd: &str, prefix: &str) {
+        let (name, arg) = cmd.split_once(char::is_whitespace).unwrap_or((cmd, ""));
+        let parse = |s: &mut Self, arg: &str| match s.opts.input.parse::<T>(arg) {
+            Ok(e) => Some(e),
+            Err(error) => {
+                s.say(error.caret(arg));
+                s.say(format_args!("{prefix}invalid expression: {error}"));
+                None
+            }
+        };
+        match name {
+            "type" if arg.trim().is_empty() => self.say(T::TYPE),
+            "type" => {
+                if let Some(e) = parse(self, arg) {
+                    match e.eval_with(&self.env) {
+                        Ok(Value::Val(_)) => self.say(T::TYPE),
+                        Ok(Value::Bool(_)) => self.say("bool"),
+                        Err(error) => self.say(format_args!("{prefix}no value: {error}")),
+                    }
+                }
+            }
+            "ast" => {
+                if let Some(e) = parse(self, arg) {
+                    let mut out = String::new();
+                    ast(&e, 0, &mut out);
+                    self.write(out);
+                }
+            }
+            "vars" => {
+                let mut out = String::new();
+                let mut vars: Vec<_> = self.env.vars().filter(|(name, _)| !name.starts_with('$')).collect();
+                vars.sort_by(|a, b| a.0.cmp(b.0));
+                for (name, v) in vars {
+                    out.push_str(&format!("{name} = {v}\n"));
+                }
+                let mut funcs: Vec<_> = self.env.funcs().collect();
+                funcs.sort_by(|a, b| a.0.cmp(b.0));
+                for (name, f) in funcs {
+                    let def = Stmt::Fn {name: name.clone(), params: f.params.clone(), body: f.body.clone()};
+                    if self.opts.full_parens {
+                        out.push_str(&format!("{def:#}\n"));
+                    } else {
+                        out.push_str(&format!("{def}\n"));
+                    }
+                }
+                self.write(out);
+            }
+            "help" => self.say(":type [EXPR]  type of values, or of the value of EXPR
+:ast EXPR     syntax tree of EXPR
+:vars         variables and their values, and user functions
+:help         this list"),
+            _ => self.say(format_args!("{prefix}unknown command :{name}, :help lists commands")),
+        }
+    }
+}
+
+/// Writes the syntax tree of `e`, a node per line indented by its depth
+fn ast<T: TBound<T>>(e: &Expr<T>, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    let children: Vec<&Expr<T>> = match e {
+        Expr::Value{v} => {
+            out.push_str(&format!("{indent}value {}\n", v.to_literal()));
+            Vec::new()
+        }
+        Expr::Var{name} => {
+            out.push_str(&format!("{indent}variable {name}\n"));
+            Vec::new()
+        }
+        Expr::Op1{op, child} => {
+            out.push_str(&format!("{indent}operator {op}\n"));
+            vec![child]
+        }
+        Expr::Op2{op, l, r} => {
+            out.push_str(&format!("{indent}operator {}\n", op.symbol::<T>()));
+            vec![l, r]
+        }
+        Expr::Call{name, args} => {
+            out.push_str(&format!("{indent}call {name}\n"));
+            args.iter().collect()
+        }
+        Expr::Cond{c, t, f} => {
+            out.push_str(&format!("{indent}conditional ?:\n"));
+            vec![c, t, f]
+        }
+    };
+    for c in children {
+        ast(c, depth + 1, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use crate::expr::Env;
+    use crate::Options;
+
+    /// Output of a session reading `input` from the prompt in type `i32`
+    fn session(input: &str) -> String {
+        let opts = Options::default();
+        let mut out = Vec::new();
+        Session::<i32, _>::with_output(Env::default(), &opts, &mut out).read(input.as_bytes());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn results() {
+        let out = session("1 + 2\n$1 * 2; $_ + 1\n$3 - $1\n$9\n");
+        assert_eq!(out, "> 1 + 2
+simplified: 3
+$1 = 3
+> $1 * 2
+simplified: $1 * 2
+$2 = 6
+$_ + 1
+simplified: $_ + 1
+$3 = 7
+> $3 - $1
+simplified: $3 - $1
+$4 = 4
+> $9
+simplified: $9
+no value: unknown variable $9 in $9
+> \n");
+    }
+
+    #[test]
+    fn commands() {
+        let out = session(":type\n:type 1 > 2\n:type y\n:ast -x + f(1)\n:ast 1 +\n:foo\n");
+        assert_eq!(out, "> i32
+> bool
+> no value: unknown variable y in y
+> operator +
+  operator -
+    variable x
+  call f
+    value 1
+> 1 +
+   ^
+invalid expression: expected value, variable, unary operator or '(', found end of input at character 3 (byte 3)
+> unknown command :foo, :help lists commands
+> \n");
+    }
+
+    #[test]
+    fn variables() {
+        let out = session("let y = 2; fn f(n) = n * y\nlet x = 1\n:vars\n");
+        assert!(out.ends_with("> x = 1\ny = 2\nfn f(n) = n * y\n> \n"), "{out}");
+        // Results are not listed
+        assert_eq!(session("1\n:vars\n"), "> 1\nsimplified: 1\n$1 = 1\n> > \n");
+    }
+}
@@ -0,0 +1,680 @@
+use crate::TBound;
+use crate::expr::{Expr, Notation, MAX_NESTING, OPERATORS};
+use crate::lexer::{tokenize, TerminalEnd, TokenKind};
+use crate::parser::{self, ParseError};
+use std::str::FromStr;
+
+/// Representation of expressions in input and output
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Repr {
+    /// Text with infix operators like `1 + 2 * 3`
+    #[default]
+    Infix,
+    /// JSON objects like `{"op":"add","l":{"value":"1"},"r":{"value":"2"}}`
+    Json,
+    /// S-expressions like `(+ 1 (* 2 3))`
+    Sexpr,
+}
+impl FromStr for Repr {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Repr, ()> {
+        match s {
+            "infix" => Ok(Repr::Infix),
+            "json" => Ok(Repr::Json),
+            "sexpr" => Ok(Repr::Sexpr),
+            _ => Err(()),
+        }
+    }
+}
+impl Repr {
+    /// Parses a single expression
+    pub fn parse<T: TBound<T>>(self, input: &str) -> Result<Expr<T>, ParseError> {
+        match self {
+            Repr::Infix => parser::parse(input),
+            Repr::Json => parse_json(input),
+            Repr::Sexpr => parse_sexpr(input),
+        }
+    }
+
+    /// Writes `e`, an infix expression with each subexpression in parentheses if `full`
+    pub fn write<T: TBound<T>>(self, e: &Expr<T>, full: bool) -> String {
+        match self {
+            Repr::Infix if full => format!("{e:#}"),
+            Repr::Infix => e.to_string(),
+            Repr::Json => e.to_json(),
+            Repr::Sexpr => e.to_sexpr(),
+        }
+    }
+}
+
+/// Whether `name` is a variable, or a function if `call`, which is displayed as an infix
+/// expression that parses back to the same name. Names of other types of tree nodes are rejected.
+fn is_name<T: TBound<T>>(name: &str, call: bool) -> bool {
+    if matches!(name, "let" | "fn") || OPERATORS.iter().any(|d| d.symbol == name) {
+        return false;
+    }
+    let text = if call { format!("{name}(") } else { name.to_string() };
+    match tokenize::<T>(&text) {
+        Ok(tokens) => matches!(tokens[0].kind, TokenKind::Ident(n) if n == name) && tokens.len() == 2 + call as usize,
+        Err(_) => false,
+    }
+}
+
+fn error(input: &str, pos: usize, expected: &'static str, found: String) -> ParseError {
+    ParseError {pos, col: input[..pos].chars().count(), expected, found}
+}
+
+impl<T: TBound<T>> Expr<T> {
+    /// The expression as JSON objects: `{"value":LITERAL}`, `{"var":NAME}`, `{"call":NAME,"args":[E...]}`,
+    /// `{"op":NAME,"child":E}` for a unary operator named by `OpDef::name`, `{"op":NAME,"l":E,"r":E}`
+    /// for a binary operator and `{"op":"cond","c":E,"t":E,"f":E}`, where a literal is a string
+    /// in the syntax of infix expressions
+    pub fn to_json(&self) -> String {
+        let mut s = String::new();
+        self.write_json(&mut s);
+        s
+    }
+
+    fn write_json(&self, s: &mut String) {
+        match self {
+            Expr::Value{v} => {
+                s.push_str("{\"value\":");
+                json_string(&v.to_literal(), s);
+            }
+            Expr::Var{name} => {
+                s.push_str("{\"var\":");
+                json_string(name, s);
+            }
+            Expr::Op1{op, child} => {
+                s.push_str("{\"op\":");
+                json_string(op.def().name, s);
+                s.push_str(",\"child\":");
+                child.write_json(s);
+            }
+            Expr::Op2{op, l, r} => {
+                s.push_str("{\"op\":");
+                json_string(op.def::<T>().name, s);
+                s.push_str(",\"l\":");
+                l.write_json(s);
+                s.push_str(",\"r\":");
+                r.write_json(s);
+            }
+            Expr::Call{name, args} => {
+                s.push_str("{\"call\":");
+                json_string(name, s);
+                s.push_str(",\"args\":[");
+                for (i, a) in args.iter().enumerate() {
+                    if i > 0 {
+                        s.push(',');
+                    }
+                    a.write_json(s);
+                }
+                s.push(']');
+            }
+            Expr::Cond{c, t, f} => {
+                s.push_str("{\"op\":\"cond\",\"c\":");
+                c.write_json(s);
+                s.push_str(",\"t\":");
+                t.write_json(s);
+                s.push_str(",\"f\":");
+                f.write_json(s);
+            }
+        }
+        s.push('}');
+    }
+
+    /// The expression as an S-expression: `(SYMBOL E)` for a prefix operator, `(E SYMBOL)` for
+    /// a postfix operator, `(SYMBOL E E)` for a binary operator, `(NAME E...)` for a call
+    /// and `(?: E E E)` for a conditional. A literal that is not a single atom, like `-1`,
+    /// is written as a string in double quotes.
+    pub fn to_sexpr(&self) -> String {
+        let mut s = String::new();
+        self.write_sexpr(&mut s);
+        s
+    }
+
+    fn write_sexpr(&self, s: &mut String) {
+        let (head, args): (&str, Vec<&Expr<T>>) = match self {
+            Expr::Value{v} => {
+                let lit = v.to_literal();
+                let value = matches!(tokenize::<T>(&lit).as_deref(), Ok([t, _]) if matches!(t.kind, TokenKind::Value(_)));
+                if value && atom(&lit) == lit.len() {
+                    s.push_str(&lit);
+                } else {
+                    s.push_str(&lit.to_literal());
+                }
+                return;
+            }
+            Expr::Var{name} => {
+                s.push_str(name);
+                return;
+            }
+            Expr::Op1{op, child} if matches!(op.def().notation, Notation::Postfix(_)) => {
+                s.push('(');
+                child.write_sexpr(s);
+                s.push(' ');
+                s.push_str(op.symbol());
+                s.push(')');
+                return;
+            }
+            Expr::Op1{op, child} => (op.symbol(), vec![child]),
+            Expr::Op2{op, l, r} => (op.symbol::<T>(), vec![l, r]),
+            Expr::Call{name, args} => (name, args.iter().collect()),
+            Expr::Cond{c, t, f} => ("?:", vec![c, t, f]),
+        };
+        s.push('(');
+        s.push_str(head);
+        for a in args {
+            s.push(' ');
+            a.write_sexpr(s);
+        }
+        s.push(')');
+    }
+}
+
+/// Appends `v` as a JSON string
+fn json_string(v: &str, s: &mut String) {
+    s.push('"');
+    for c in v.chars() {
+        match c {
+            '"' | '\\' => {
+                s.push('\\');
+                s.push(c);
+            }
+            '\n' => s.push_str("\\n"),
+            '\r' => s.push_str("\\r"),
+            '\t' => s.push_str("\\t"),
+            c if c.is_control() => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+}
+
+/// A JSON value and the byte offset of its first character in the input
+struct Node {
+    pos: usize,
+    v: Json,
+}
+enum Json {
+    /// `null`, `true` or `false`, which are not used by expressions
+    Constant,
+    /// The text of a number
+    Number(String),
+    Str(String),
+    Array(Vec<Node>),
+    /// Members with the byte offsets of their names, and the byte offset of the closing `}`
+    Object(Vec<(String, usize, Node)>, usize),
+}
+
+struct Reader<'a> {
+    input: &'a str,
+    /// Byte offset of the next character
+    pos: usize,
+    /// Number of objects and arrays of JSON, or lists, containing the next character, at most
+    /// `MAX_NESTING`, which bounds the recursion of parsing and of the expression parsed
+    depth: usize,
+}
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    /// An error at `pos`, where the JSON token or the character found is reported
+    fn error(&self, pos: usize, expected: &'static str) -> ParseError {
+        let rest = &self.input[pos..];
+        let word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '.' | '+' | '-');
+        let n = match rest.chars().next() {
+            None => return error(self.input, pos, expected, String::from("end of input")),
+            Some('"') => String::literal(rest),
+            Some(c) if word(c) => rest.find(|c| !word(c)).unwrap_or(rest.len()),
+            Some(c) => c.len_utf8(),
+        };
+        error(self.input, pos, expected, format!("'{}'", &rest[..n]))
+    }
+
+    fn ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    /// Consumes `s` if the input continues with it
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.rest().starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    fn value(&mut self) -> Result<Node, ParseError> {
+        self.ws();
+        let pos = self.pos;
+        let v = match self.rest().chars().next() {
+            Some('{' | '[') if self.depth == MAX_NESTING => return Err(self.error(pos, "less deeply nested value")),
+            Some(c @ ('{' | '[')) => {
+                // The depth is not restored after an error, which ends parsing
+                self.depth += 1;
+                let v = if c == '{' { self.object()? } else { self.array()? };
+                self.depth -= 1;
+                v
+            }
+            Some('"') => Json::Str(self.string()?),
+            Some('-' | '0'..='9') => self.number()?,
+            _ if self.eat("null") || self.eat("true") || self.eat("false") => Json::Constant,
+            _ => return Err(self.error(pos, "JSON value")),
+        };
+        Ok(Node {pos, v})
+    }
+
+    fn object(&mut self) -> Result<Json, ParseError> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.ws();
+        if self.eat("}") {
+            return Ok(Json::Object(members, self.pos - 1));
+        }
+        loop {
+            self.ws();
+            let pos = self.pos;
+            if !self.rest().starts_with('"') {
+                return Err(self.error(pos, "member name"));
+            }
+            let name = self.string()?;
+            self.ws();
+            if !self.eat(":") {
+                return Err(self.error(self.pos, "':'"));
+            }
+            members.push((name, pos, self.value()?));
+            self.ws();
+            if self.eat("}") {
+                return Ok(Json::Object(members, self.pos - 1));
+            }
+            if !self.eat(",") {
+                return Err(self.error(self.pos, "',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, ParseError> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.ws();
+        if self.eat("]") {
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.ws();
+            if self.eat("]") {
+                return Ok(Json::Array(items));
+            }
+            if !self.eat(",") {
+                return Err(self.error(self.pos, "',' or ']'"));
+            }
+        }
+    }
+
+    /// A string with escapes `\"`, `\\`, `\/`, `\b`, `\f`, `\n`, `\r`, `\t` and `\uXXXX`,
+    /// which may be a surrogate pair
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.pos += 1;
+        let mut v = String::new();
+        loop {
+            let pos = self.pos;
+            let Some(c) = self.rest().chars().next() else {
+                return Err(self.error(pos, "'\"'"));
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(v),
+                '\\' => {}
+                c if c.is_control() => return Err(self.error(pos, "'\"'")),
+                c => {
+                    v.push(c);
+                    continue;
+                }
+            }
+            let c = match self.rest().chars().next() {
+                Some(c @ ('"' | '\\' | '/')) => c,
+                Some('b') => '\u{8}',
+                Some('f') => '\u{c}',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('u') => {
+                    self.pos += 1;
+                    let hi = self.hex4()?;
+                    let code = if (0xd800..0xdc00).contains(&hi) {
+                        // A high surrogate is only valid followed by the escape of a low surrogate
+                        let low = self.pos;
+                        let lo = if self.rest().starts_with("\\u") {
+                            self.pos += 2;
+                            self.hex4()?
+                        } else {
+                            0
+                        };
+                        if !(0xdc00..0xe000).contains(&lo) {
+                            return Err(self.error(low, "escape of a low surrogate"));
+                        }
+                        0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00)
+                    } else {
+                        hi
+                    };
+                    v.push(char::from_u32(code).ok_or_else(|| self.error(pos, "valid escape sequence"))?);
+                    continue;
+                }
+                _ => return Err(self.error(pos, "valid escape sequence")),
+            };
+            self.pos += 1;
+            v.push(c);
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let hex = self.rest().get(..4).filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()));
+        let v = hex.and_then(|h| u32::from_str_radix(h, 16).ok()).ok_or_else(|| self.error(self.pos, "4 hexadecimal digits"))?;
+        self.pos += 4;
+        Ok(v)
+    }
+
+    fn number(&mut self) -> Result<Json, ParseError> {
+        let start = self.pos;
+        let digits = |r: &mut Reader| {
+            let n = r.rest().find(|c: char| !c.is_ascii_digit()).unwrap_or(r.rest().len());
+            r.pos += n;
+            n
+        };
+        self.eat("-");
+        if !self.eat("0") && digits(self) == 0 {
+            return Err(self.error(self.pos, "digit"));
+        }
+        if self.eat(".") && digits(self) == 0 {
+            return Err(self.error(self.pos, "digit"));
+        }
+        if self.eat("e") || self.eat("E") {
+            let _ = self.eat("+") || self.eat("-");
+            if digits(self) == 0 {
+                return Err(self.error(self.pos, "digit"));
+            }
+        }
+        Ok(Json::Number(self.input[start..self.pos].to_string()))
+    }
+
+    fn string_of<'n>(&self, n: &'n Node, expected: &'static str) -> Result<&'n str, ParseError> {
+        match &n.v {
+            Json::Str(s) => Ok(s),
+            _ => Err(self.error(n.pos, expected)),
+        }
+    }
+
+    /// The expression of a JSON node in the schema of `Expr::to_json`
+    fn json_expr<T: TBound<T>>(&self, n: &Node) -> Result<Expr<T>, ParseError> {
+        let Json::Object(members, end) = &n.v else {
+            return Err(self.error(n.pos, "object"));
+        };
+        let get = |name: &str| members.iter().find(|m| m.0 == name).map(|m| &m.2);
+        let member = |name: &str, expected| get(name).ok_or_else(|| self.error(*end, expected));
+        let expr = |name: &str, expected| member(name, expected).and_then(|n| self.json_expr::<T>(n));
+        let (e, names): (Expr<T>, &[&str]) = if let Some(v) = get("value") {
+            // A number is accepted as the text of a literal
+            let lit = match &v.v {
+                Json::Number(s) | Json::Str(s) => s,
+                _ => return Err(self.error(v.pos, "value")),
+            };
+            let v = T::parse_literal(lit).ok_or_else(|| self.error(v.pos, "value"))?;
+            (Expr::Value {v}, &["value"])
+        } else if let Some(v) = get("var") {
+            let name = self.string_of(v, "variable name")?;
+            if !is_name::<T>(name, false) {
+                return Err(self.error(v.pos, "variable name"));
+            }
+            (Expr::Var {name: name.to_string()}, &["var"])
+        } else if let Some(v) = get("call") {
+            let name = self.string_of(v, "function name")?;
+            if !is_name::<T>(name, true) {
+                return Err(self.error(v.pos, "function name"));
+            }
+            let a = member("args", "member \"args\"")?;
+            let Json::Array(items) = &a.v else {
+                return Err(self.error(a.pos, "array"));
+            };
+            let args = items.iter().map(|a| self.json_expr(a)).collect::<Result<_, _>>()?;
+            (Expr::Call {name: name.to_string(), args}, &["call", "args"])
+        } else if let Some(v) = get("op") {
+            let name = self.string_of(v, "operator name")?;
+            if name == "cond" {
+                let (c, t, f) = (expr("c", "member \"c\"")?, expr("t", "member \"t\"")?, expr("f", "member \"f\"")?);
+                (Expr::Cond {c: Box::new(c), t: Box::new(t), f: Box::new(f)}, &["op", "c", "t", "f"])
+            } else {
+                let def = OPERATORS.iter().find(|d| d.name == name && d.applies::<T>()).ok_or_else(|| self.error(v.pos, "operator name"))?;
+                match def.notation {
+                    Notation::Prefix(op) | Notation::Postfix(op) => {
+                        (Expr::Op1 {op, child: Box::new(expr("child", "member \"child\"")?)}, &["op", "child"])
+                    }
+                    Notation::Infix(op, _) => {
+                        let (l, r) = (expr("l", "member \"l\"")?, expr("r", "member \"r\"")?);
+                        (Expr::Op2 {op, l: Box::new(l), r: Box::new(r)}, &["op", "l", "r"])
+                    }
+                }
+            }
+        } else {
+            return Err(self.error(*end, "member \"value\", \"var\", \"call\" or \"op\""));
+        };
+        for (i, (name, pos, _)) in members.iter().enumerate() {
+            if !names.contains(&name.as_str()) {
+                return Err(self.error(*pos, "member of the node"));
+            }
+            if members[..i].iter().any(|m| m.0 == *name) {
+                return Err(self.error(*pos, "distinct member name"));
+            }
+        }
+        Ok(e)
+    }
+}
+
+/// Parses an expression in the JSON schema of `Expr::to_json`
+pub fn parse_json<T: TBound<T>>(input: &str) -> Result<Expr<T>, ParseError> {
+    let mut r = Reader {input, pos: 0, depth: 0};
+    let n = r.value()?;
+    r.ws();
+    if r.pos < input.len() {
+        return Err(r.error(r.pos, "end of input"));
+    }
+    r.json_expr(&n)
+}
+
+/// Length of the atom at the start of `s`, a string in double quotes or the characters
+/// up to whitespace or a parenthesis
+fn atom(s: &str) -> usize {
+    if s.starts_with('"') {
+        return String::literal(s);
+    }
+    s.find(|c: char| c.is_whitespace() || c == '(' || c == ')').unwrap_or(s.len())
+}
+
+enum Sexpr<'a> {
+    /// An atom and its byte offset in the input
+    Atom(usize, &'a str),
+    /// A list and the byte offsets of its parentheses
+    List(usize, Vec<Sexpr<'a>>, usize),
+}
+
+impl<'a> Reader<'a> {
+    fn sexpr(&mut self) -> Result<Sexpr<'a>, ParseError> {
+        self.pos = self.input.len() - self.rest().trim_start().len();
+        let start = self.pos;
+        if self.rest().is_empty() || self.rest().starts_with(')') {
+            return Err(self.error(start, "value, variable or '('"));
+        }
+        if !self.eat("(") {
+            let n = atom(self.rest());
+            self.pos += n;
+            return Ok(Sexpr::Atom(start, &self.input[start..self.pos]));
+        }
+        if self.depth == MAX_NESTING {
+            return Err(self.error(start, "less deeply nested value"));
+        }
+        self.depth += 1;
+        let mut items = Vec::new();
+        loop {
+            self.pos = self.input.len() - self.rest().trim_start().len();
+            if self.eat(")") {
+                self.depth -= 1;
+                return Ok(Sexpr::List(start, items, self.pos - 1));
+            }
+            if self.rest().is_empty() {
+                return Err(self.error(self.pos, "value, variable, '(' or ')'"));
+            }
+            items.push(self.sexpr()?);
+        }
+    }
+
+    fn sexpr_atom<T: TBound<T>>(&self, pos: usize, a: &str) -> Result<Expr<T>, ParseError> {
+        if let Ok(tokens) = tokenize::<T>(a) {
+            match &tokens[0].kind {
+                TokenKind::Value(v) if tokens.len() == 2 => return Ok(Expr::Value {v: v.clone()}),
+                TokenKind::Ident(name) if is_name::<T>(name, false) && *name == a => {
+                    return Ok(Expr::Var {name: a.to_string()});
+                }
+                _ => {}
+            }
+        }
+        // The literal of a value that is not a single atom
+        match String::parse_literal(a).filter(|_| a.starts_with('"')).and_then(|lit| T::parse_literal(&lit)) {
+            Some(v) => Ok(Expr::Value {v}),
+            None => Err(error(self.input, pos, "value or variable", format!("'{a}'"))),
+        }
+    }
+
+    /// The expression of an S-expression in the syntax of `Expr::to_sexpr`
+    fn sexpr_expr<T: TBound<T>>(&self, s: &Sexpr) -> Result<Expr<T>, ParseError> {
+        let (items, end) = match s {
+            Sexpr::Atom(pos, a) => return self.sexpr_atom(*pos, a),
+            Sexpr::List(_, items, end) => (items, *end),
+        };
+        let postfix = |s: &Sexpr| OPERATORS.iter().find_map(|d| match (s, d.notation) {
+            (Sexpr::Atom(_, a), Notation::Postfix(op)) if d.symbol == *a && d.applies::<T>() => Some(op),
+            _ => None,
+        });
+        if let [child, op] = &items[..] {
+            if let Some(op) = postfix(op) {
+                return Ok(Expr::Op1 {op, child: Box::new(self.sexpr_expr(child)?)});
+            }
+        }
+        let (pos, head) = match items.first() {
+            Some(Sexpr::Atom(pos, a)) => (*pos, *a),
+            Some(Sexpr::List(pos, ..)) => return Err(self.error(*pos, "operator or function name")),
+            None => return Err(self.error(end, "operator or function name")),
+        };
+        let args = &items[1..];
+        let prefix = OPERATORS.iter().find_map(|d| match d.notation {
+            Notation::Prefix(op) if d.symbol == head && d.applies::<T>() => Some(op),
+            _ => None,
+        });
+        let infix = OPERATORS.iter().find_map(|d| match d.notation {
+            Notation::Infix(op, _) if d.symbol == head && d.applies::<T>() => Some(op),
+            _ => None,
+        });
+        // Number of operands, a symbol of both a prefix and a binary operator like `-` is
+        // the prefix operator with a single operand
+        let arity = match (prefix, infix) {
+            (Some(_), Some(_)) if args.len() <= 1 => 1,
+            (Some(_), None) => 1,
+            (_, Some(_)) => 2,
+            _ if head == "?:" => 3,
+            _ if is_name::<T>(head, true) => {
+                let args = args.iter().map(|a| self.sexpr_expr(a)).collect::<Result<_, _>>()?;
+                return Ok(Expr::Call {name: head.to_string(), args});
+            }
+            _ => return Err(error(self.input, pos, "operator or function name", format!("'{head}'"))),
+        };
+        if args.len() < arity {
+            return Err(self.error(end, "operand"));
+        }
+        if let Some(extra) = args.get(arity) {
+            let (Sexpr::Atom(pos, _) | Sexpr::List(pos, ..)) = extra;
+            return Err(self.error(*pos, "')'"));
+        }
+        let mut args = args.iter().map(|a| self.sexpr_expr::<T>(a).map(Box::new));
+        let mut next = || args.next().unwrap();
+        Ok(match (arity, prefix, infix) {
+            (1, Some(op), _) => Expr::Op1 {op, child: next()?},
+            (2, _, Some(op)) => Expr::Op2 {op, l: next()?, r: next()?},
+            _ => Expr::Cond {c: next()?, t: next()?, f: next()?},
+        })
+    }
+}
+
+/// Parses an expression in the syntax of `Expr::to_sexpr`
+pub fn parse_sexpr<T: TBound<T>>(input: &str) -> Result<Expr<T>, ParseError> {
+    let mut r = Reader {input, pos: 0, depth: 0};
+    let s = r.sexpr()?;
+    r.pos = input.len() - r.rest().trim_start().len();
+    if r.pos < input.len() {
+        return Err(r.error(r.pos, "end of input"));
+    }
+    r.sexpr_expr(&s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_json, parse_sexpr};
+    use crate::expr::{Expr, MAX_NESTING};
+    use crate::tests::with_stack;
+
+    fn string(escaped: &str) -> Result<String, String> {
+        match parse_json::<String>(&format!("{{\"value\":\"\\\"{escaped}\\\"\"}}")) {
+            Ok(Expr::Value{v}) => Ok(v),
+            Ok(e) => panic!("not a value: {e}"),
+            Err(e) => Err(format!("{e}")),
+        }
+    }
+
+    #[test]
+    fn surrogate_pair() {
+        assert_eq!(string("\\ud83d\\ude00").unwrap(), "\u{1f600}");
+        assert_eq!(string("\\uD800\\uDC00x").unwrap(), "\u{10000}x");
+        assert_eq!(string("\\udbff\\udfff").unwrap(), "\u{10ffff}");
+    }
+
+    #[test]
+    fn lone_surrogate() {
+        assert!(string("\\ud800").unwrap_err().contains("expected escape of a low surrogate"));
+        assert!(string("\\ud800x").unwrap_err().contains("expected escape of a low surrogate"));
+        assert!(string("\\udc00").unwrap_err().contains("expected valid escape sequence"));
+        assert!(string("x\\udfff\\ud800").unwrap_err().contains("expected valid escape sequence"));
+    }
+
+    #[test]
+    fn invalid_surrogate_pair() {
+        assert!(string("\\ud800\\u0041").unwrap_err().contains("expected escape of a low surrogate"));
+        assert!(string("\\ud800\\ud800").unwrap_err().contains("expected escape of a low surrogate"));
+        assert!(string("\\udbff\\ue000").unwrap_err().contains("expected escape of a low surrogate"));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let json = |n: usize| format!("{}{{\"var\":\"x\"}}{}", "{\"op\":\"neg\",\"child\":".repeat(n), "}".repeat(n));
+        let sexpr = |n: usize| format!("{}x{}", "(- ".repeat(n), ")".repeat(n));
+        with_stack(move || {
+            assert!(parse_json::<i32>(&json(MAX_NESTING - 1)).is_ok());
+            let e = parse_json::<i32>(&json(MAX_NESTING)).err().unwrap();
+            assert_eq!((e.expected, e.pos), ("less deeply nested value", MAX_NESTING * 20));
+            assert!(parse_json::<i32>(&"[".repeat(1_000_000)).is_err());
+            assert!(parse_sexpr::<i32>(&sexpr(MAX_NESTING)).is_ok());
+            let e = parse_sexpr::<i32>(&sexpr(MAX_NESTING + 1)).err().unwrap();
+            assert_eq!((e.expected, e.pos), ("less deeply nested value", MAX_NESTING * 3));
+            assert!(parse_sexpr::<i32>(&"(".repeat(1_000_000)).is_err());
+        });
+    }
+
+    #[test]
+    fn operators_of_type() {
+        let xor = r#"{"op": "bitxor", "l": {"value": "1"}, "r": {"value": "2"}}"#;
+        assert!(parse_json::<i32>(xor).is_ok());
+        assert!(parse_json::<f64>(xor).is_err());
+        assert!(parse_sexpr::<i32>("(^ 1 2)").is_ok_and(|e| matches!(e, Expr::Op2{..})));
+        assert!(parse_sexpr::<f64>("(** 2 3)").is_ok_and(|e| e.to_sexpr() == "(^ 2 3)"));
+    }
+}
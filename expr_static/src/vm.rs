@@ -0,0 +1,206 @@
+use crate::TBound;
+use crate::expr::{Env, Expr, ExprError, MAX_NESTING, Op1Kind, Op2Kind, Stmt};
+use crate::ops::*;
+
+/// An instruction of the stack machine. Operands `at` are subexpressions whose texts are reported
+/// in errors, `target` is an index of an instruction.
+enum Instr<'a, T: 'static> {
+    Push(T),
+    Load {var: usize, at: &'a Expr<T>},
+    Op1 {op: Op1Kind, at: &'a Expr<T>},
+    Op2 {op: Op2Kind, at: &'a Expr<T>},
+    /// Checks that the top of the stack is a value of `T` usable as an argument of a built-in function
+    Arg {at: &'a Expr<T>},
+    /// Calls a built-in function, or the user function `name` in the environment if `func` is `None`
+    /// from evaluation nested `level` deep
+    Call {func: Option<&'static Func<T>>, name: &'a str, argc: usize, level: usize, at: &'a Expr<T>},
+    /// Pops the left operand of `&&` or `||`, if it determines the result, pushes it and jumps
+    Test {op: Op2Kind, at: &'a Expr<T>, target: usize},
+    /// Converts the right operand of `&&` or `||` on the top of the stack to a boolean
+    Bool {op: Op2Kind, at: &'a Expr<T>},
+    /// Pops the condition of `?:` and jumps if it is false
+    Branch {at: &'a Expr<T>, target: usize},
+    Jump {target: usize},
+    /// Fails, as evaluation of `at` is nested deeper than `expr::MAX_NESTING`
+    Nesting {at: &'a Expr<T>},
+}
+
+/// An expression compiled to a sequence of instructions of a stack machine. Evaluation yields
+/// the same results and errors as `Expr::eval_with`. Bodies of user functions are evaluated
+/// by the tree walking interpreter.
+pub struct Program<'a, T: 'static> {
+    code: Vec<Instr<'a, T>>,
+    vars: Vec<&'a str>,
+    depth: usize,
+}
+
+impl<'a, T: TBound<T>> Program<'a, T> {
+    pub fn compile(e: &'a Expr<T>) -> Program<'a, T> {
+        let mut p = Program {code: Vec::new(), vars: Vec::new(), depth: 0};
+        p.emit(e, 0, 0);
+        p
+    }
+
+    /// Appends instructions evaluating `e`, with `sp` values already on the stack, nested `level`
+    /// deep in evaluation. A subexpression nested too deep is compiled to an instruction failing
+    /// where the tree walking interpreter fails, so compilation recurses at most as deep.
+    fn emit(&mut self, e: &'a Expr<T>, sp: usize, level: usize) {
+        self.depth = self.depth.max(sp + 1);
+        if level >= MAX_NESTING {
+            self.code.push(Instr::Nesting {at: e});
+            return;
+        }
+        let level = level + 1;
+        match e {
+            Expr::Value{v} => self.code.push(Instr::Push(v.clone())),
+            Expr::Var{name} => {
+                let var = match self.vars.iter().position(|v| v == name) {
+                    Some(i) => i,
+                    None => {
+                        self.vars.push(name);
+                        self.vars.len() - 1
+                    }
+                };
+                self.code.push(Instr::Load {var, at: e});
+            }
+            Expr::Op1{op, child} => {
+                self.emit(child, sp, level);
+                self.code.push(Instr::Op1 {op: *op, at: e});
+            }
+            Expr::Op2{op: op @ (Op2Kind::And | Op2Kind::Or), l, r} => {
+                self.emit(l, sp, level);
+                let test = self.code.len();
+                self.code.push(Instr::Test {op: *op, at: e, target: 0});
+                self.emit(r, sp, level);
+                self.code.push(Instr::Bool {op: *op, at: e});
+                let end = self.code.len();
+                if let Instr::Test{target, ..} = &mut self.code[test] {
+                    *target = end;
+                }
+            }
+            Expr::Op2{op, l, r} => {
+                self.emit(l, sp, level);
+                self.emit(r, sp + 1, level);
+                self.code.push(Instr::Op2 {op: *op, at: e});
+            }
+            Expr::Call{name, args} => {
+                let func = find_func::<T>(name);
+                for (i, a) in args.iter().enumerate() {
+                    self.emit(a, sp + i, level);
+                    // Arguments of user functions may be booleans
+                    if func.is_some() {
+                        self.code.push(Instr::Arg {at: a});
+                    }
+                }
+                self.code.push(Instr::Call {func, name, argc: args.len(), level, at: e});
+            }
+            Expr::Cond{c, t, f} => {
+                self.emit(c, sp, level);
+                let branch = self.code.len();
+                self.code.push(Instr::Branch {at: e, target: 0});
+                self.emit(t, sp, level);
+                let jump = self.code.len();
+                self.code.push(Instr::Jump {target: 0});
+                let else_start = self.code.len();
+                self.emit(f, sp, level);
+                let end = self.code.len();
+                if let Instr::Branch{target, ..} = &mut self.code[branch] {
+                    *target = else_start;
+                }
+                if let Instr::Jump{target} = &mut self.code[jump] {
+                    *target = end;
+                }
+            }
+        }
+    }
+
+    /// Gets values of variables used by the program from `env`
+    pub fn bind(&self, env: &Env<T>) -> Vec<Option<Value<T>>> {
+        self.vars.iter().map(|name| env.get(name).cloned()).collect()
+    }
+
+    /// An error of evaluating `at`, whose text is only built here
+    fn error(error: EvalError, at: &Expr<T>) -> ExprError {
+        ExprError {error, at: at.to_string()}
+    }
+
+    /// Evaluates the program with values of variables `vars`, ordered as returned by `bind`,
+    /// and user functions of `env`
+    pub fn run(&self, vars: &[Option<Value<T>>], env: &Env<T>) -> Result<Value<T>, ExprError> {
+        let mut stack: Vec<Value<T>> = Vec::with_capacity(self.depth);
+        let mut args: Vec<T> = Vec::new();
+        let mut pc = 0;
+        while let Some(instr) = self.code.get(pc) {
+            pc += 1;
+            match instr {
+                Instr::Push(v) => stack.push(Value::Val(v.clone())),
+                Instr::Load{var, at} => match &vars[*var] {
+                    Some(v) => stack.push(v.clone()),
+                    None => return Err(Self::error(EvalError::UnknownVariable(self.vars[*var].to_string()), at)),
+                },
+                Instr::Op1{op, at} => {
+                    let c = stack.pop().unwrap();
+                    stack.push(op.eval(c).map_err(|e| Self::error(e, at))?);
+                }
+                Instr::Op2{op, at} => {
+                    let r = stack.pop().unwrap();
+                    let l = stack.pop().unwrap();
+                    stack.push(op.eval(l, r).map_err(|e| Self::error(e, at))?);
+                }
+                Instr::Arg{at} => {
+                    if let Some(Value::Bool(_)) = stack.last() {
+                        return Err(Self::error(EvalError::Unsupported {op: "call", ty: "bool"}, at));
+                    }
+                }
+                Instr::Call{func: None, name, argc, level, at} => {
+                    let vals: Vec<Value<T>> = stack.drain(stack.len() - argc..).collect();
+                    stack.push(env.call(name, &vals, 0, *level, || at.to_string())?);
+                }
+                Instr::Call{func: Some(func), argc, at, ..} => {
+                    args.clear();
+                    args.extend(stack.drain(stack.len() - argc..).map(|a| match a {
+                        Value::Val(v) => v,
+                        Value::Bool(_) => unreachable!("checked by Instr::Arg"),
+                    }));
+                    stack.push(Value::Val(func.call(&args).map_err(|e| Self::error(e, at))?));
+                }
+                Instr::Test{op, at, target} => {
+                    let b = stack.pop().unwrap().bool(op.symbol::<T>()).map_err(|e| Self::error(e, at))?;
+                    if b == matches!(op, Op2Kind::Or) {
+                        stack.push(Value::Bool(b));
+                        pc = *target;
+                    }
+                }
+                Instr::Bool{op, at} => {
+                    let b = stack.pop().unwrap().bool(op.symbol::<T>()).map_err(|e| Self::error(e, at))?;
+                    stack.push(Value::Bool(b));
+                }
+                Instr::Branch{at, target} => {
+                    if !stack.pop().unwrap().bool("?:").map_err(|e| Self::error(e, at))? {
+                        pc = *target;
+                    }
+                }
+                Instr::Jump{target} => pc = *target,
+                Instr::Nesting{at} => return Err(Self::error(EvalError::NestingLimit(MAX_NESTING), at)),
+            }
+        }
+        Ok(stack.pop().unwrap())
+    }
+}
+
+/// Executes a statement like `Stmt::exec`, but evaluates the expression by the stack machine
+pub fn exec<T: TBound<T>>(stmt: &Stmt<T>, env: &mut Env<T>) -> Result<Option<Value<T>>, ExprError> {
+    match stmt {
+        Stmt::Let{name, e} => {
+            let p = Program::compile(e);
+            let v = p.run(&p.bind(env), env)?;
+            env.set(name, v.clone());
+            Ok(Some(v))
+        }
+        Stmt::Fn{..} => stmt.exec(env),
+        Stmt::Expr(e) => {
+            let p = Program::compile(e);
+            p.run(&p.bind(env), env).map(Some)
+        }
+    }
+}